@@ -1,12 +1,16 @@
 //! connect 4 solver
-use classic::minimax::find_best_move;
-use std::{cmp, fmt};
-use std::ops::{Index,IndexMut};
+use classic::minimax::Searcher;
+use classic::protocol::serve;
+use classic::record::{replay_file, GameRecord, GameResult, Mode};
+use classic::transposition::TranspositionTable;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
 extern crate classic;
-use classic::board::{Piece, Board, Move};
+use classic::board::{Piece, Board, Move, Notation, NotationError, Outcome};
 use text_io::read;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 enum C4Piece {
     B,
     R,
@@ -37,153 +41,205 @@ impl fmt::Display for C4Piece {
     }
 }
 
+// The (column, row) steps of the four directions a line can run in
+const DIRECTIONS: [(i16, i16); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+// Size of the board, how many in a row win, and whether players may pop
+// their own pieces out of the bottom of a column instead of dropping one
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Geometry {
+    columns: u8,
+    rows: u8,
+    connect: u8,
+    pop_out: bool,
+}
 
-fn generate_segments(num_columns: u8, num_rows: u8, segment_length: u8) -> Vec<Vec<(u8,u8)>> {
-    let segments: Vec<Vec<(u8,u8)>> = Vec::new();
-    // generate the vertical segments
-    for c in 0..num_columns {
-        for r in 0..(NUM_ROWS - SEGMENT_LENGTH + 1) {
-            let mut segment: Vec<(u8,u8)> = Vec::new();
-            for t in 0..SEGMENT_LENGTH {
-                segment.push((c, r + t));
-            }
-            segments.push(segment);
+impl Default for Geometry {
+    fn default() -> Geometry {
+        Geometry {
+            columns: 7,
+            rows: 6,
+            connect: 4,
+            pop_out: false,
         }
     }
+}
 
-    // generate the horizontal segments
-    for c in 0..(num_columns - SEGMENT_LENGTH + 1) {
-        for r in 0..NUM_ROWS {
-            let mut segment: Vec<(u8,u8)> = Vec::new();
-            for t in 0..SEGMENT_LENGTH {
-                segment.push((c + t, r));
-            }
-            segments.push(segment);
+// Each column takes rows + 1 bits of a u128, bottom square first. The
+// spare bit on top of every column keeps lines from wrapping round from
+// the top of one column into the bottom of the next.
+impl Geometry {
+    fn new(columns: u8, rows: u8, connect: u8, pop_out: bool) -> Result<Geometry, String> {
+        if columns == 0 || rows == 0 || columns as u32 * (rows as u32 + 1) > 128 {
+            return Err(format!("a {}x{} board does not fit in a bitboard", columns, rows));
         }
-    }
-
-    // generate the bottom left to top right diagonal segments
-    for c in 0..(num_columns - SEGMENT_LENGTH + 1) {
-        for r in 0..NUM_ROWS - SEGMENT_LENGTH + 1 {
-            let mut segment: Vec<(u8,u8)> = Vec::new();
-            for t in 0..SEGMENT_LENGTH {
-                segment.push((c + t, r + t));
-            }
-            segments.push(segment);
+        // has_line shifts by up to rows + 2 for the diagonals
+        if rows as u32 + 2 >= 128 {
+            return Err(format!("a {}x{} board is too tall for a bitboard", columns, rows));
         }
+        if connect < 2 || connect > columns.max(rows) {
+            return Err(format!("cannot connect {} on a {}x{} board", connect, columns, rows));
+        }
+        Ok(Geometry { columns, rows, connect, pop_out })
     }
 
-    // generate the top left to bottom right diagonal segments
-    for c in 0..num_columns - SEGMENT_LENGTH + 1 {
-        for r in (SEGMENT_LENGTH - 1)..NUM_ROWS {
-            let mut segment: Vec<(u8,u8)> = Vec::new();
-            for t in 0..SEGMENT_LENGTH {
-                segment.push((c + t, r - t));
-            }
-            segments.push(segment);
-        }
+    fn column_bits(&self) -> u8 {
+        self.rows + 1
     }
-    segments
-}
 
+    fn square(&self, column: u8, row: u8) -> u128 {
+        1 << (column * self.column_bits() + row)
+    }
 
-#[derive(Clone,Debug,Eq,PartialEq)]
-struct C4Column(Vec<C4Piece>);
+    fn column_mask(&self, column: u8) -> u128 {
+        ((1 << self.rows) - 1) << (column * self.column_bits())
+    }
 
-impl C4Column {
-    fn new(n: usize) -> C4Column {
-        C4Column((0..n).map(|_| C4Piece::E).collect::<Vec<_>>())
+    fn bottom_row(&self) -> u128 {
+        (0..self.columns).fold(0, |mask, c| mask | self.square(c, 0))
     }
 
-    fn full(&self) -> bool {
-         self.0.len() == NUM_ROWS as usize
-     }
+    // Whether mask holds connect squares in a line. Shifting by the
+    // distance between neighbouring squares and masking leaves the squares
+    // that start a line one longer each time.
+    fn has_line(&self, mask: u128) -> bool {
+        DIRECTIONS.iter().any(|&(dc, dr)| {
+            let shift = (dc as i32 * self.column_bits() as i32 + dr as i32).unsigned_abs();
+            let mut starts = mask;
+            for _ in 1..self.connect {
+                starts &= starts >> shift;
+            }
+            starts != 0
+        })
+    }
 
-    fn push(&self, item: C4Piece) {
-        if self.full() {
-            panic!("Trying to push piece to full column");
+    // The segment of connect squares starting at (column, row) and heading
+    // in direction, if it fits on the board. Coordinates are i16 so that
+    // segments running off the largest boards do not overflow.
+    fn segment(&self, column: i16, row: i16, (dc, dr): (i16, i16)) -> Option<u128> {
+        let last = self.connect as i16 - 1;
+        let (end_column, end_row) = (column + last * dc, row + last * dr);
+        let on_board =
+            |c: i16, r: i16| c >= 0 && c < self.columns as i16 && r >= 0 && r < self.rows as i16;
+        if !on_board(column, row) || !on_board(end_column, end_row) {
+            return None;
         }
-        self.0.push(item);
+        Some((0..=last).fold(0, |mask, t| {
+            mask | self.square((column + t * dc) as u8, (row + t * dr) as u8)
+        }))
     }
-}
 
-impl Index<usize> for C4Column {
-    type Output = C4Piece;
-    fn index(&self, index: usize) -> &Self::Output {
-        if index as usize > self.0.len() - 1 {
-            return &mut C4Piece::E;
-        }
-        &self.0[index]
+    // Every segment that (column, row) is part of
+    fn segments_through(self, column: u8, row: u8) -> impl Iterator<Item = u128> {
+        DIRECTIONS.iter().flat_map(move |&(dc, dr)| {
+            (0..self.connect as i16)
+                .filter_map(move |k| self.segment(column as i16 - k * dc, row as i16 - k * dr, (dc, dr)))
+        })
     }
-}
 
-impl IndexMut<usize> for C4Column {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        if index as usize > self.0.len() - 1 {
-            return &mut C4Piece::E;
-        }
-        &mut self.0[index]
+    // Every segment on the board
+    fn all_segments(self) -> impl Iterator<Item = u128> {
+        (0..self.columns as i16).flat_map(move |c| {
+            (0..self.rows as i16)
+                .flat_map(move |r| DIRECTIONS.iter().filter_map(move |&d| self.segment(c, r, d)))
+        })
     }
 }
 
-const NUM_ROWS: u8 = 6;
-const NUM_COLS: u8 = 7;
-const SEGMENT_LENGTH: u8 = 4;
-
-#[derive(Clone,Debug,Eq,PartialEq)]
+// Bitboard: a mask of the squares of each color. Moves 0 to columns - 1
+// drop a piece in that column, and in Pop-Out columns to 2 * columns - 1
+// take the bottom piece out of column move - columns.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 struct C4Board {
-    position: Vec<C4Column>,
+    geometry: Geometry,
+    black: u128,
+    red: u128,
     turn: C4Piece,
-    segments: Vec<Vec<(u8,u8)>>,
+    score: i32, // sum of segment_score over all segments, kept up to date by make_move
 }
 
 impl C4Board {
-    fn new(position: Option<Vec<C4Column>>, turn: C4Piece) -> C4Board {
+    fn new(geometry: Geometry, turn: C4Piece) -> C4Board {
         // turn default: C4Piece::B
-        let position = match position {
-            None => (0..NUM_COLS).map(|_| C4Column::new(NUM_ROWS as usize))
-                .collect::<Vec<_>>(),
-            Some(p) => p,
-        };
-        let segments: Vec<Vec<(u8,u8)>> = generate_segments(NUM_COLS, NUM_ROWS, SEGMENT_LENGTH);
-        C4Board { position, turn, segments }
-
-    }
-    /// Returns the count of black & red pieces in a segment
-    fn count_segment(&self, segment: Vec<(u8,u8)>) -> (u8,u8) {
-        let mut black_count: u8 = 0;
-        let mut red_count: u8 = 0;
-        for (column, row) in segment {
-            if self.position[column as usize][row as usize] == C4Piece::B {
-                black_count += 1;
-            } else if self.position[column as usize][row as usize] == C4Piece::R {
-                red_count += 1;
-            }
+        C4Board { geometry, black: 0, red: 0, turn, score: 0 }
+    }
+
+    fn from_masks(geometry: Geometry, black: u128, red: u128, turn: C4Piece) -> C4Board {
+        let mut board = C4Board { geometry, black, red, turn, score: 0 };
+        board.score = board.full_score();
+        board
+    }
+
+    fn full_score(&self) -> i32 {
+        self.geometry.all_segments().map(|s| self.segment_score(s)).sum()
+    }
+
+    fn occupied(&self) -> u128 {
+        self.black | self.red
+    }
+
+    // The pieces of player
+    fn pieces(&self, player: C4Piece) -> u128 {
+        match player {
+            C4Piece::B => self.black,
+            C4Piece::R => self.red,
+            C4Piece::E => 0,
         }
-        return (black_count, red_count)
     }
-    fn evaluate_segment(self, segment: Vec<(u8,u8)>, player: C4Piece) -> f32 {
-        let (black_count, red_count) = self.count_segment(segment);
-        if red_count > 0 && black_count > 0 {
-            return 0.0; // mixed segments are neutral
+
+    fn piece_at(&self, column: u8, row: u8) -> C4Piece {
+        let bit = self.geometry.square(column, row);
+        if self.black & bit != 0 {
+            C4Piece::B
+        } else if self.red & bit != 0 {
+            C4Piece::R
+        } else {
+            C4Piece::E
         }
-        let count = cmp::max(red_count, black_count);
-        let mut score: f32 = 0.0;
-        if count == 2 {
-            score = 1.0;
-        } else if count == 3 {
-            score = 100.0;
-        } else if count == 4 {
-            score = 1000000.0;
+    }
+
+    fn height(&self, column: u8) -> u8 {
+        (self.occupied() & self.geometry.column_mask(column)).count_ones() as u8
+    }
+
+    /// How good a segment is for black: lines one and two short of
+    /// connect and complete lines of one color count, mixed segments are
+    /// neutral
+    fn segment_score(&self, segment: u128) -> i32 {
+        let black_count = (self.black & segment).count_ones();
+        let red_count = (self.red & segment).count_ones();
+        if red_count > 0 && black_count > 0 {
+            return 0; // mixed segments are neutral
         }
-        let mut color: C4Piece = C4Piece::B;
+        let connect = self.geometry.connect as u32;
+        let count = red_count.max(black_count);
+        let score = if count == connect {
+            1000000
+        } else if count + 1 == connect {
+            100
+        } else if count + 2 == connect && count > 0 {
+            1
+        } else {
+            0
+        };
         if red_count > black_count {
-            color = C4Piece::R;
-        }
-        if color != player {
-            return -score;
+            -score
+        } else {
+            score
         }
-        score
+    }
+
+    // The board after the bottom piece of column is taken out and the
+    // rest drop down a square
+    fn pop(&self, column: u8) -> C4Board {
+        let column_mask = self.geometry.column_mask(column);
+        let drop = |mask: u128| (mask & !column_mask) | ((mask & column_mask) >> 1 & column_mask);
+        let mut board = *self;
+        board.black = drop(self.black);
+        board.red = drop(self.red);
+        board.turn = self.turn.opposite();
+        board.score = board.full_score();
+        board
     }
 }
 
@@ -193,38 +249,84 @@ impl Board<C4Piece> for C4Board {
     }
 
     fn make_move(&self, location: Move) -> C4Board {
-        let temp_position: Vec<C4Column> = self.position.clone();
-        for c in 0..NUM_COLS {
-            temp_position[c as usize] = self.position[c as usize].clone();
+        let columns = self.geometry.columns as Move;
+        if location >= columns {
+            return self.pop((location - columns) as u8);
+        }
+        let column = location as u8;
+        let row = self.height(column);
+        let bit = self.geometry.square(column, row);
+        let mut board = *self;
+        match self.turn {
+            C4Piece::B => board.black |= bit,
+            _ => board.red |= bit,
+        }
+        board.turn = self.turn.opposite();
+        // only the segments through the new piece change score
+        for s in self.geometry.segments_through(column, row) {
+            board.score += board.segment_score(s) - self.segment_score(s);
         }
-        temp_position[location as usize].push(self.turn);
-         C4Board { position: temp_position, turn: self.turn.opposite(), segments: self.segments.clone() }
-     }
+        board
+    }
 
     fn legal_moves(&self) -> Vec<Move> {
-        (0..NUM_COLS).filter(|&c| !self.position[c as usize].full())
+        let geometry = self.geometry;
+        let drops = (0..geometry.columns).filter(|&c| self.height(c) < geometry.rows);
+        let own = self.pieces(self.turn);
+        let pops = (0..geometry.columns)
+            .filter(|&c| geometry.pop_out && own & geometry.square(c, 0) != 0)
+            .map(|c| c + geometry.columns);
+        drops.chain(pops)
             .map(|x| x as i32)
             .collect::<Vec<_>>()
     }
 
     fn is_win(&self) -> bool {
-        for segment in self.segments {
-            let (black_count, red_count) = self.count_segment(segment);
-            if black_count == 4 || red_count == 4 {
-                return true;
-            }
+        self.geometry.has_line(self.black) || self.geometry.has_line(self.red)
+    }
+
+    fn outcome(&self) -> Option<Outcome> {
+        // a pop can complete lines for both players, or only for the
+        // opponent: the player who popped wins the first and loses the
+        // second
+        if self.geometry.has_line(self.pieces(self.turn.opposite())) {
+            Some(Outcome::Loss)
+        } else if self.geometry.has_line(self.pieces(self.turn)) {
+            Some(Outcome::Win)
+        } else if self.legal_moves().is_empty() {
+            Some(Outcome::Draw)
+        } else {
+            None
         }
-         false
-     }
+    }
 
+    fn evaluate(&self, player: C4Piece) -> f32 {
+        match player {
+            C4Piece::R => -self.score as f32,
+            _ => self.score as f32,
+        }
+    }
 
+    fn hash_key(&self) -> Option<u64> {
+        // the red squares and a bit on top of every column's pieces tell
+        // black from red from empty, then the halves are mixed into 64 bits
+        let key = self.red + self.occupied() + self.geometry.bottom_row();
+        let turn = (self.turn == C4Piece::R) as u64;
+        let mixed = (key as u64) ^ ((key >> 64) as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        Some(mixed ^ turn.wrapping_mul(0xc2b2_ae3d_27d4_eb4f))
+    }
 
-    fn evaluate(&self, player: C4Piece) -> f32 {
-        let mut total: f32 = 0.0;
-        for segment in self.segments {
-            total += self.evaluate_segment(segment, player);
+    fn move_score(&self, location: Move) -> f32 {
+        // central columns take part in the most segments, and dropping
+        // usually beats popping
+        let columns = self.geometry.columns as Move;
+        let column = location % columns;
+        let center_distance = (2 * column - (columns - 1)).abs() as f32 / 2.0;
+        if location >= columns {
+            -center_distance - columns as f32
+        } else {
+            -center_distance
         }
-        return total;
     }
 }
 
@@ -232,55 +334,343 @@ impl Board<C4Piece> for C4Board {
 impl fmt::Display for C4Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut display = String::new();
-        for r in (0..NUM_ROWS).rev() {
-            display.push_str("|");
-            for c in 0..NUM_COLS {
-                display.push_str(&format!("{}", self.position[c as usize][r as usize]));
-                display.push_str("|");
+        for r in (0..self.geometry.rows).rev() {
+            display.push('|');
+            for c in 0..self.geometry.columns {
+                display.push_str(&format!("{}", self.piece_at(c, r)));
+                display.push('|');
             }
-            display.push_str("\n");
+            display.push('\n');
         }
         write!(f, "{}", display)
     }
 }
 
 
+// The rows from top to bottom separated by slashes, with . for an empty
+// square, then the side to move: ".../...R.../...BR.. B" with all six rows.
+// Other board sizes have other numbers of rows and squares, and games
+// that are not connect four or use the Pop-Out rule end in the connect
+// length and "popout": ".../...BR.. B 5 popout".
+impl Notation for C4Board {
+    fn to_notation(&self) -> String {
+        let geometry = self.geometry;
+        let rows: Vec<String> = (0..geometry.rows)
+            .rev()
+            .map(|r| (0..geometry.columns).map(|c| piece_char(self.piece_at(c, r))).collect())
+            .collect();
+        let mut notation = format!("{} {}", rows.join("/"), piece_char(self.turn));
+        if geometry.connect != 4 || geometry.pop_out {
+            notation.push_str(&format!(" {}", geometry.connect));
+        }
+        if geometry.pop_out {
+            notation.push_str(" popout");
+        }
+        notation
+    }
+}
+
+fn piece_char(piece: C4Piece) -> char {
+    match piece {
+        C4Piece::B => 'B',
+        C4Piece::R => 'R',
+        C4Piece::E => '.',
+    }
+}
+
+impl FromStr for C4Board {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<C4Board, NotationError> {
+        let err = |reason: &str| NotationError(format!("{}: {}", reason, s));
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let (squares, turn, variant) = match fields.as_slice() {
+            [squares, turn, variant @ ..] if variant.len() <= 2 => (squares, turn, variant),
+            _ => return Err(err("expected squares, side to move, connect length and popout")),
+        };
+        let connect = match variant.first() {
+            Some(n) => n.parse::<u8>().map_err(|_| err("connect length must be a number"))?,
+            None => 4,
+        };
+        let pop_out = match variant.get(1) {
+            Some(&"popout") => true,
+            Some(_) => return Err(err("the last field can only be popout")),
+            None => false,
+        };
+        let rows: Vec<Vec<char>> = squares.split('/').map(|row| row.chars().collect()).collect();
+        let columns = rows[0].len();
+        if rows.iter().any(|row| row.len() != columns) || columns > u8::MAX as usize || rows.len() > u8::MAX as usize {
+            return Err(err("rows must all have the same number of squares"));
+        }
+        let geometry = Geometry::new(columns as u8, rows.len() as u8, connect, pop_out).map_err(|e| err(&e))?;
+        let (mut black, mut red) = (0, 0);
+        // fill the columns from the bottom row up
+        for (r, row) in rows.iter().rev().enumerate() {
+            for (c, &piece) in row.iter().enumerate() {
+                let (c, r) = (c as u8, r as u8);
+                if piece != '.' && r > 0 && (black | red) & geometry.square(c, r - 1) == 0 {
+                    return Err(err("pieces cannot float above empty squares"));
+                }
+                match piece {
+                    'B' => black |= geometry.square(c, r),
+                    'R' => red |= geometry.square(c, r),
+                    '.' => continue,
+                    _ => return Err(err("squares must be B, R or .")),
+                }
+            }
+        }
+        let turn = match *turn {
+            "B" => C4Piece::B,
+            "R" => C4Piece::R,
+            _ => return Err(err("side to move must be B or R")),
+        };
+        Ok(C4Board::from_masks(geometry, black, red, turn))
+    }
+}
+
 fn get_player_move<B: Board<P>, P: Piece>(board: &B) -> Move {
     loop {
         let line: String = read!("{}\n");
-        let player_move: Move = line.parse::<Move>().unwrap();
-        if board.legal_moves().contains(&player_move) {
-            return player_move;
+        // ignore anything that is not a legal move
+        if let Ok(player_move) = line.trim().parse::<Move>() {
+            if board.legal_moves().contains(&player_move) {
+                return player_move;
+            }
+        }
+    }
+}
+
+const USAGE: &str =
+    "usage: connect4 [--columns N] [--rows N] [--connect N] [--pop-out] [--replay FILE | --save FILE | --protocol]";
+
+// Board geometry and mode from the command line
+fn parse_args(args: &[String]) -> Result<(Geometry, Mode), String> {
+    let standard = Geometry::default();
+    let (mut columns, mut rows, mut connect) = (standard.columns, standard.rows, standard.connect);
+    let mut pop_out = false;
+    let mut mode = Mode::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if mode.parse_option(arg, &mut args)? {
+            continue;
+        }
+        let mut value = || args.next().cloned().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--columns" | "--rows" | "--connect" => {
+                let n = value()?.parse().map_err(|_| format!("{} needs a number", arg))?;
+                match arg.as_str() {
+                    "--columns" => columns = n,
+                    "--rows" => rows = n,
+                    _ => connect = n,
+                }
+            }
+            "--pop-out" => pop_out = true,
+            _ => return Err(format!("unknown option {}", arg)),
         }
     }
+    Ok((Geometry::new(columns, rows, connect, pop_out)?, mode))
 }
 
 fn main() {
     // main game loop
-
-    let position = Some((0..9).map(|_| C4Piece::E).collect::<Vec<_>>());
-    let mut board = C4Board::new(position, C4Piece::R);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (geometry, mode) = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let mut board = C4Board::new(geometry, C4Piece::R);
+    let save_path = match mode {
+        Mode::Replay(path) => return replay_file(&path, board).expect("cannot replay games"),
+        Mode::Protocol => {
+            let stdin = std::io::stdin();
+            return serve("Connect Four", board, stdin.lock(), std::io::stdout()).expect("protocol I/O failed");
+        }
+        Mode::Play(save_path) => save_path,
+    };
+    // search as deep as a couple of seconds allows, solving the game
+    // outright towards the end
+    let mut searcher = Searcher {
+        max_depth: geometry.columns as i32 * geometry.rows as i32,
+        time_limit: Some(Duration::from_secs(2)),
+        table: Some(TranspositionTable::new(64 << 20)),
+        ..Searcher::default()
+    };
+    println!("Connect {} on {} columns and {} rows{}. Columns are numbered from 0{}.",
+             geometry.connect, geometry.columns, geometry.rows,
+             if geometry.pop_out { " with Pop-Out" } else { "" },
+             if geometry.pop_out { ", add the number of columns to pop" } else { "" });
+    let mut record = GameRecord::new("Connect Four");
+    if geometry != Geometry::default() {
+        record.set_tag("Position", board.to_notation());
+    }
     loop {
         let human_move = get_player_move(&board);
         board = board.make_move(human_move);
+        record.moves.push(human_move);
         println!("{}", board);
-        if board.is_win() {
-            println!("You win!");
-            break;
-        } else if board.is_draw() {
-            println!("Draw!");
-            break;
+        match board.outcome() {
+            Some(Outcome::Loss) => { println!("You win!"); break; }
+            Some(Outcome::Win) => { println!("I win!"); break; }
+            Some(Outcome::Draw) => { println!("Draw!"); break; }
+            None => {}
         }
-        let computer_move = find_best_move(board.clone(), 8);
+        let computer_move = searcher.search(&board).best_move.unwrap();
         println!("My move is {}", computer_move);
         board = board.make_move(computer_move);
+        record.moves.push(computer_move);
         println!("{}", board);
-        if board.is_win() {
-            println!("I win!");
-            break;
-        } else if board.is_draw() {
-            println!("Draw!");
-            break;
+        match board.outcome() {
+            Some(Outcome::Loss) => { println!("I win!"); break; }
+            Some(Outcome::Win) => { println!("You win!"); break; }
+            Some(Outcome::Draw) => { println!("Draw!"); break; }
+            None => {}
+        }
+    }
+    record.result = GameResult::from_board(&board, C4Piece::R);
+    if let Some(path) = save_path {
+        std::fs::write(&path, record.to_string()).expect("cannot save game");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use classic::minimax::{find_best_move, WIN_SCORE};
+
+    #[test]
+    fn test_notation() {
+        let board = C4Board::new(Geometry::default(), C4Piece::R).make_move(3).make_move(3).make_move(4);
+        let text = board.to_notation();
+        assert_eq!(text, "......./......./......./......./...B.../...RR.. B");
+        assert_eq!(text.parse(), Ok(board));
+        assert!("......./......./......./......./...B.../...RR..".parse::<C4Board>().is_err());
+        // fewer rows is a smaller board, not an error
+        let short: C4Board = "......./......./......./...B.../...RR.. B".parse().unwrap();
+        assert_eq!(short.geometry.rows, 5);
+        assert!("......./......./....../...B.../...RR.. B".parse::<C4Board>().is_err());
+        assert!("......./......./......./...B.../......./...RR.. B".parse::<C4Board>().is_err());
+        assert!("......./......./......./......./...B.../...RX.. B".parse::<C4Board>().is_err());
+    }
+
+    #[test]
+    fn test_win_and_block() {
+        // R takes the open column rather than let B complete it
+        let board: C4Board = "......./......./......./B....../B....../B..RR.. R".parse().unwrap();
+        assert_eq!(find_best_move(board, 2), 0);
+        // and completes a row of its own when it can
+        let board: C4Board = "......./......./......./......./B....../BB.RRR. R".parse().unwrap();
+        let answer = find_best_move(board, 2);
+        assert!(answer == 2 || answer == 6);
+    }
+
+    #[test]
+    fn test_bitboard() {
+        // incremental scores and shift based wins agree with checking
+        // every segment from scratch
+        use rand::prelude::*;
+        let mut rng: StdRng = SeedableRng::seed_from_u64(7);
+        for _ in 0..50 {
+            let mut board = C4Board::new(Geometry::default(), C4Piece::B);
+            while !board.is_win() && !board.is_draw() {
+                let moves = board.legal_moves();
+                board = board.make_move(moves[rng.gen_range(0, moves.len())]);
+                let fresh = C4Board::from_masks(board.geometry, board.black, board.red, board.turn);
+                assert_eq!(board.score, fresh.score);
+                let four = board.geometry.all_segments().any(|s| board.black & s == s || board.red & s == s);
+                assert_eq!(board.is_win(), four);
+                assert_eq!(board.to_notation().parse(), Ok(board));
+            }
         }
     }
+
+    #[test]
+    fn test_forced_loss() {
+        // R can only block one end of B's three in a row
+        let board: C4Board = "......./......./......./......./......./.BBB.RR R".parse().unwrap();
+        let mut searcher = Searcher {
+            max_depth: 6,
+            table: Some(TranspositionTable::new(1 << 20)),
+            ..Searcher::default()
+        };
+        let result = searcher.search(&board);
+        assert_eq!(result.score, -(WIN_SCORE - 2.0)); // lost on B's move
+        assert_eq!(result.pv.len(), 2);
+    }
+
+    #[test]
+    fn test_connect_five() {
+        let geometry = Geometry::new(9, 7, 5, false).unwrap();
+        let mut board = C4Board::new(geometry, C4Piece::R);
+        for &location in &[4, 4, 5, 5, 6, 6, 3, 3] {
+            board = board.make_move(location);
+        }
+        assert_eq!(board.to_notation(), "........./........./........./........./........./...BBBB../...RRRR.. R 5");
+        assert_eq!(board.to_notation().parse(), Ok(board));
+        // four in a row is not enough
+        assert_eq!(board.outcome(), None);
+        assert_eq!(board.make_move(2).outcome(), Some(Outcome::Loss));
+        assert!(Geometry::new(7, 6, 8, false).is_err());
+        assert!(Geometry::new(20, 6, 4, false).is_err());
+        // one tall column still needs room for the diagonal shifts
+        assert!(Geometry::new(1, 126, 100, false).is_err());
+        let geometry = Geometry::new(1, 125, 100, false).unwrap();
+        let mut board = C4Board::new(geometry, C4Piece::R);
+        for _ in 0..125 {
+            assert_eq!(board.outcome(), None);
+            board = board.make_move(0);
+        }
+        assert_eq!(board.outcome(), Some(Outcome::Draw));
+    }
+
+    #[test]
+    fn test_game_record() {
+        use classic::record::step_through;
+        let empty = C4Board::new(Geometry::default(), C4Piece::R);
+        let record: GameRecord = "[Game \"Connect Four\"]\n\n1. 0 1 2. 0 1 3. 0 1 4. 0 1-0\n".parse().unwrap();
+        let board = record.final_position(empty).unwrap();
+        assert_eq!(GameResult::from_board(&board, C4Piece::R), record.result);
+        assert_eq!(record.to_string().parse(), Ok(record));
+        // other geometries are kept in the Position tag
+        let geometry = Geometry::new(9, 7, 5, true).unwrap();
+        let start = C4Board::new(geometry, C4Piece::R);
+        let mut record = GameRecord::new("Connect Four");
+        record.set_tag("Position", start.to_notation());
+        record.moves = vec![4, 4, 13];
+        let record: GameRecord = record.to_string().parse().unwrap();
+        let initial = record.initial_position(empty).unwrap();
+        assert_eq!(initial, start);
+        assert_eq!(record.final_position(initial).unwrap(), start.make_move(4).make_move(4).make_move(13));
+
+        // stepping through shows every position in turn
+        let mut output = Vec::new();
+        step_through(std::slice::from_ref(&record), empty, "\n\n\n".as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let shown: Vec<&str> = output.lines().filter(|l| !l.starts_with('|')).collect();
+        assert_eq!(shown, ["", "Move 4", "", "Move 4", "", "Move 13", "", "Result *"]);
+        assert!(output.starts_with(&format!("{}\n", start)));
+    }
+
+    #[test]
+    fn test_pop_out() {
+        let board: C4Board = "......./......./......./...R.../...B.../...RB.. R 4 popout".parse().unwrap();
+        assert_eq!(board.to_notation(), "......./......./......./...R.../...B.../...RB.. R 4 popout");
+        // R may only pop its own bottom piece
+        let mut moves = board.legal_moves();
+        moves.sort();
+        assert_eq!(moves, vec![0, 1, 2, 3, 4, 5, 6, 10]);
+        let popped = board.make_move(10);
+        assert_eq!(popped.to_notation(), "......./......./......./......./...R.../...BB.. B 4 popout");
+        assert_eq!(popped, C4Board::from_masks(popped.geometry, popped.black, popped.red, popped.turn));
+        // a pop that makes lines for both players wins for the popper
+        let board: C4Board = "......./......./......./R....../BRRR.../RBBB..B R 4 popout".parse().unwrap();
+        assert_eq!(board.outcome(), None);
+        let popped = board.make_move(7);
+        assert_eq!(popped.to_notation(), "......./......./......./......./RRRR.../BBBB..B B 4 popout");
+        assert_eq!(popped.outcome(), Some(Outcome::Loss));
+        // and popping is not allowed without Pop-Out
+        assert!(!"......./......./......./R....../BRRR.../RBBB..B R".parse::<C4Board>().unwrap().legal_moves().contains(&7));
+    }
 }
@@ -0,0 +1,509 @@
+//! mancala games with stores: Kalah and Congkak
+use fnv::FnvHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::time::Duration;
+
+extern crate classic;
+use classic::board::{Board, Move, Notation, NotationError, Outcome, Piece};
+use classic::minimax::Searcher;
+use classic::protocol::serve;
+use text_io::read;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum MancalaPiece {
+    P1,
+    P2,
+}
+
+impl Piece for MancalaPiece {
+    fn opposite(&self) -> MancalaPiece {
+        match self {
+            MancalaPiece::P1 => MancalaPiece::P2,
+            MancalaPiece::P2 => MancalaPiece::P1,
+        }
+    }
+}
+
+impl fmt::Display for MancalaPiece {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                MancalaPiece::P1 => "1",
+                MancalaPiece::P2 => "2",
+            }
+        )
+    }
+}
+
+// Both games sow into the player's own store but not the opponent's, and a
+// move that ends in the player's store earns another move straight away.
+// A move ending in an empty house of the player's own captures the seeds
+// opposite along with the last seed, if there are any opposite. In Congkak
+// a move ending in a house that already held seeds carries on by picking
+// them all up and sowing them (relay sowing).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Rules {
+    Kalah,
+    Congkak,
+}
+
+// Relay sowing can in principle go round forever, so a Congkak move stops
+// after picking up seeds this many times
+const MAX_RELAYS: u32 = 100;
+
+// Each player's houses from left to right as they see them, followed by
+// their store: player 1's houses, store 1, player 2's houses, store 2.
+// Moves are the numbers of the houses in this order, so player 1 plays 0
+// to houses - 1 and player 2 houses + 1 to 2 * houses.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct MancalaBoard {
+    rules: Rules,
+    houses: usize, // per player
+    pits: Vec<u8>,
+    turn: MancalaPiece,
+}
+
+impl MancalaBoard {
+    // Kalah(6, 4) is the usual game, Congkak is played with seven houses
+    // of seven seeds
+    fn new(rules: Rules, houses: usize, seeds: u8) -> MancalaBoard {
+        let mut pits = vec![seeds; 2 * houses + 2];
+        pits[houses] = 0;
+        pits[2 * houses + 1] = 0;
+        MancalaBoard { rules, houses, pits, turn: MancalaPiece::P1 }
+    }
+
+    fn store(&self, player: MancalaPiece) -> usize {
+        match player {
+            MancalaPiece::P1 => self.houses,
+            MancalaPiece::P2 => 2 * self.houses + 1,
+        }
+    }
+
+    // The houses of player
+    fn side(&self, player: MancalaPiece) -> std::ops::Range<usize> {
+        match player {
+            MancalaPiece::P1 => 0..self.houses,
+            MancalaPiece::P2 => self.houses + 1..2 * self.houses + 1,
+        }
+    }
+
+    fn seeds_on_side(&self, player: MancalaPiece) -> u32 {
+        self.pits[self.side(player)].iter().map(|&n| n as u32).sum()
+    }
+
+    fn total_seeds(&self) -> u32 {
+        self.pits.iter().map(|&n| n as u32).sum()
+    }
+
+    // The house across the board from house
+    fn opposite_house(&self, house: usize) -> usize {
+        2 * self.houses - house
+    }
+
+    // The pit after pit when player sows, skipping the opponent's store
+    fn next_pit(&self, pit: usize, player: MancalaPiece) -> usize {
+        let next = (pit + 1) % self.pits.len();
+        if next == self.store(player.opposite()) {
+            (next + 1) % self.pits.len()
+        } else {
+            next
+        }
+    }
+
+    // The game ends when either player has no seeds left in their houses,
+    // and is decided as soon as a store holds more than half the seeds
+    fn is_over(&self) -> bool {
+        let half = self.total_seeds() / 2;
+        self.seeds_on_side(MancalaPiece::P1) == 0
+            || self.seeds_on_side(MancalaPiece::P2) == 0
+            || self.pits[self.store(MancalaPiece::P1)] as u32 > half
+            || self.pits[self.store(MancalaPiece::P2)] as u32 > half
+    }
+
+    // Final scores once the game is over, with the seeds left in each
+    // player's houses going to their store
+    fn final_scores(&self) -> (u32, u32) {
+        (
+            self.pits[self.store(MancalaPiece::P1)] as u32 + self.seeds_on_side(MancalaPiece::P1),
+            self.pits[self.store(MancalaPiece::P2)] as u32 + self.seeds_on_side(MancalaPiece::P2),
+        )
+    }
+}
+
+impl Board<MancalaPiece> for MancalaBoard {
+    fn turn(&self) -> MancalaPiece {
+        self.turn
+    }
+    fn make_move(&self, location: Move) -> MancalaBoard {
+        let player = self.turn;
+        let mut b = self.clone();
+        let mut pit = location as usize;
+        let mut seeds = b.pits[pit];
+        b.pits[pit] = 0;
+        let mut relays = 0;
+        loop {
+            for _ in 0..seeds {
+                pit = b.next_pit(pit, player);
+                b.pits[pit] += 1;
+            }
+            if pit == b.store(player) {
+                return b; // another move for the same player
+            }
+            if b.rules == Rules::Congkak && b.pits[pit] > 1 && relays < MAX_RELAYS {
+                seeds = b.pits[pit];
+                b.pits[pit] = 0;
+                relays += 1;
+                continue;
+            }
+            let opposite = b.opposite_house(pit);
+            if b.side(player).contains(&pit) && b.pits[pit] == 1 && b.pits[opposite] > 0 {
+                let store = b.store(player);
+                b.pits[store] += b.pits[opposite] + 1;
+                b.pits[pit] = 0;
+                b.pits[opposite] = 0;
+            }
+            break;
+        }
+        b.turn = player.opposite();
+        b
+    }
+    fn legal_moves(&self) -> Vec<Move> {
+        if self.is_over() {
+            return Vec::new();
+        }
+        self.side(self.turn)
+            .filter(|&h| self.pits[h] > 0)
+            .map(|h| h as Move)
+            .collect()
+    }
+    fn is_win(&self) -> bool {
+        // decided one way or the other, see outcome
+        matches!(self.outcome(), Some(Outcome::Win) | Some(Outcome::Loss))
+    }
+    fn is_draw(&self) -> bool {
+        self.outcome() == Some(Outcome::Draw)
+    }
+    fn evaluate(&self, player: MancalaPiece) -> f32 {
+        self.pits[self.store(player)] as f32 - self.pits[self.store(player.opposite())] as f32
+    }
+    fn outcome(&self) -> Option<Outcome> {
+        if !self.is_over() {
+            return None;
+        }
+        // whoever has more seeds wins
+        let (score1, score2) = self.final_scores();
+        let (own, other) = match self.turn {
+            MancalaPiece::P1 => (score1, score2),
+            MancalaPiece::P2 => (score2, score1),
+        };
+        if own > other {
+            Some(Outcome::Win)
+        } else if own < other {
+            Some(Outcome::Loss)
+        } else {
+            Some(Outcome::Draw)
+        }
+    }
+    fn hash_key(&self) -> Option<u64> {
+        let mut hasher = FnvHasher::default();
+        (&self.pits, self.turn).hash(&mut hasher);
+        Some(hasher.finish())
+    }
+    fn move_score(&self, location: Move) -> f32 {
+        // captures first, then moves that earn another move
+        let after = self.make_move(location);
+        let store = self.store(self.turn);
+        let gain = (after.pits[store] - self.pits[store]) as f32;
+        if after.turn == self.turn {
+            gain + 0.5
+        } else {
+            gain
+        }
+    }
+}
+
+impl fmt::Display for MancalaBoard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row = |houses: &mut dyn Iterator<Item = &u8>| houses.map(|n| format!("{:3}", n)).collect::<String>();
+        let (side1, side2) = (self.side(MancalaPiece::P1), self.side(MancalaPiece::P2));
+        let numbers: String = (1..=self.houses).map(|h| format!("{:3}", h)).collect();
+        write!(
+            f,
+            "{:3} |{}\n     {} | {}\n     {}",
+            self.pits[self.store(MancalaPiece::P2)],
+            row(&mut self.pits[side2].iter().rev()),
+            row(&mut self.pits[side1].iter()),
+            self.pits[self.store(MancalaPiece::P1)],
+            numbers
+        )
+    }
+}
+
+// Seeds in player 1's houses, then in player 2's houses, the player to move
+// and the two stores, then congkak for Congkak:
+// "4,4,4,4,4,4/4,4,4,4,4,4 1 0 0"
+impl Notation for MancalaBoard {
+    fn to_notation(&self) -> String {
+        let houses = |side: &[u8]| side.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(",");
+        let mut notation = format!(
+            "{}/{} {} {} {}",
+            houses(&self.pits[self.side(MancalaPiece::P1)]),
+            houses(&self.pits[self.side(MancalaPiece::P2)]),
+            self.turn,
+            self.pits[self.store(MancalaPiece::P1)],
+            self.pits[self.store(MancalaPiece::P2)]
+        );
+        if self.rules == Rules::Congkak {
+            notation.push_str(" congkak");
+        }
+        notation
+    }
+}
+
+impl FromStr for MancalaBoard {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<MancalaBoard, NotationError> {
+        let err = |reason: &str| NotationError(format!("{}: {}", reason, s));
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let (sides, turn, store1, store2, rules) = match fields.as_slice() {
+            [sides, turn, store1, store2] => (sides, turn, store1, store2, Rules::Kalah),
+            [sides, turn, store1, store2, "congkak"] => (sides, turn, store1, store2, Rules::Congkak),
+            _ => return Err(err("expected houses, player to move, two stores and the rules")),
+        };
+        let sides = sides
+            .split('/')
+            .map(|side| {
+                side.split(',')
+                    .map(|n| n.parse::<u8>().map_err(|_| err("seeds must be numbers")))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let houses = sides[0].len();
+        if sides.len() != 2 || sides[1].len() != houses {
+            return Err(err("expected two rows of the same number of houses"));
+        }
+        let store = |n: &str| n.parse::<u8>().map_err(|_| err("stores must be numbers"));
+        let mut pits = sides[0].clone();
+        pits.push(store(store1)?);
+        pits.extend_from_slice(&sides[1]);
+        pits.push(store(store2)?);
+        // all the seeds could end up in one pit
+        if pits.iter().map(|&n| n as u32).sum::<u32>() > u8::MAX as u32 {
+            return Err(err("too many seeds"));
+        }
+        let turn = match *turn {
+            "1" => MancalaPiece::P1,
+            "2" => MancalaPiece::P2,
+            _ => return Err(err("player to move must be 1 or 2")),
+        };
+        Ok(MancalaBoard { rules, houses, pits, turn })
+    }
+}
+
+fn get_player_move(board: &MancalaBoard) -> Move {
+    loop {
+        let line: String = read!("{}\n");
+        // ignore anything that is not a legal move
+        if let Ok(house) = line.trim().parse::<Move>() {
+            if board.legal_moves().contains(&(house - 1)) {
+                return house - 1;
+            }
+        }
+    }
+}
+
+const USAGE: &str = "usage: mancala [--congkak] [--houses N] [--seeds N] [--protocol]";
+
+// Starting position and whether to speak the engine protocol, from the
+// command line
+fn parse_args(args: &[String]) -> Result<(MancalaBoard, bool), String> {
+    let mut rules = Rules::Kalah;
+    let (mut houses, mut seeds) = (None, None);
+    let mut protocol = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut number = || {
+            args.next()
+                .and_then(|n| n.parse::<u8>().ok())
+                .filter(|&n| n > 0)
+                .ok_or(format!("{} needs a positive number", arg))
+        };
+        match arg.as_str() {
+            "--congkak" => rules = Rules::Congkak,
+            "--houses" => houses = Some(number()? as usize),
+            "--seeds" => seeds = Some(number()?),
+            "--protocol" => protocol = true,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    let standard = match rules {
+        Rules::Kalah => 6,
+        Rules::Congkak => 7,
+    };
+    let (houses, seeds) = (houses.unwrap_or(standard), seeds.unwrap_or(standard as u8 - 2));
+    if 2 * houses as u32 * seeds as u32 > u8::MAX as u32 {
+        return Err(format!("{} houses of {} seeds are too many seeds", houses, seeds));
+    }
+    Ok((MancalaBoard::new(rules, houses, seeds), protocol))
+}
+
+fn main() {
+    // main game loop
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (mut board, protocol) = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let game = match board.rules {
+        Rules::Kalah => "Kalah",
+        Rules::Congkak => "Congkak",
+    };
+    if protocol {
+        let stdin = std::io::stdin();
+        return serve(game, board, stdin.lock(), std::io::stdout()).expect("protocol I/O failed");
+    }
+    let mut searcher = Searcher {
+        max_depth: 40,
+        time_limit: Some(Duration::from_secs(2)),
+        ..Searcher::default()
+    };
+    println!("Welcome to {}! You are Player 1 with the bottom row and the store on the right.\n\
+              Your houses are labelled 1-{}. Enter a number to move. Good luck!", game, board.houses);
+    println!("{}", board);
+    while board.outcome().is_none() {
+        // moves ending in your store earn another move
+        if board.turn() == MancalaPiece::P1 {
+            let human_move = get_player_move(&board);
+            board = board.make_move(human_move);
+        } else {
+            let computer_move = searcher.search(&board).best_move.unwrap();
+            println!("My move is {}", computer_move - board.houses as Move);
+            board = board.make_move(computer_move);
+        }
+        println!("{}", board);
+    }
+    let (score1, score2) = board.final_scores();
+    if score1 > score2 {
+        println!("You win {} to {}!", score1, score2);
+    } else if score1 < score2 {
+        println!("I win {} to {}!", score2, score1);
+    } else {
+        println!("Draw!");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use classic::minimax::{analyze, WIN_SCORE};
+    use classic::transposition::TranspositionTable;
+    use rand::prelude::*;
+
+    // Plain minimax that knows about extra turns, for checking the search
+    fn minimax(board: &MancalaBoard, depth: i32, ply: i32) -> f32 {
+        match board.outcome() {
+            Some(Outcome::Win) => return WIN_SCORE - ply as f32,
+            Some(Outcome::Loss) => return -WIN_SCORE + ply as f32,
+            Some(Outcome::Draw) => return 0.0,
+            None if depth == 0 => return board.evaluate(board.turn()),
+            None => {}
+        }
+        board
+            .legal_moves()
+            .iter()
+            .map(|&m| {
+                let child = board.make_move(m);
+                let score = minimax(&child, depth - 1, ply + 1);
+                if child.turn() == board.turn() {
+                    score
+                } else {
+                    -score
+                }
+            })
+            .fold(f32::NEG_INFINITY, f32::max)
+    }
+
+    #[test]
+    fn test_kalah() {
+        // the last seed in the store earns another move
+        let board = MancalaBoard::new(Rules::Kalah, 6, 4);
+        assert_eq!(board.to_notation(), "4,4,4,4,4,4/4,4,4,4,4,4 1 0 0");
+        let board = board.make_move(2);
+        assert_eq!(board.to_notation(), "4,4,0,5,5,5/4,4,4,4,4,4 1 1 0");
+        assert_eq!(board.to_notation().parse(), Ok(board.clone()));
+        // sowing past the opponent's store
+        let board: MancalaBoard = "0,0,0,0,0,10/1,1,1,1,1,1 1 10 10".parse().unwrap();
+        assert_eq!(board.make_move(5).to_notation(), "1,1,0,0,0,0/2,2,2,0,2,2 2 14 10");
+        // ending in an empty house of one's own captures what is opposite
+        let board: MancalaBoard = "1,0,0/0,2,0 1 0 0".parse().unwrap();
+        let board = board.make_move(0);
+        assert_eq!(board.to_notation(), "0,0,0/0,0,0 2 3 0");
+        assert_eq!(board.outcome(), Some(Outcome::Loss));
+        assert!(board.is_win() && !board.is_draw());
+        // a game can end level
+        let board: MancalaBoard = "0,0/0,1 1 1 0".parse().unwrap();
+        assert_eq!(board.outcome(), Some(Outcome::Draw));
+        assert!(board.is_draw() && !board.is_win());
+        let board: MancalaBoard = "1,0,0/2,0,1 1 0 0".parse().unwrap();
+        assert_eq!(board.make_move(0).to_notation(), "0,1,0/2,0,1 2 0 0");
+        assert!("4,4,4/4,4 1 0 0".parse::<MancalaBoard>().is_err());
+        assert!("4,4,4/4,4,4 1 0 0 oware".parse::<MancalaBoard>().is_err());
+        assert!("1,255/0,1 1 0 0".parse::<MancalaBoard>().is_err());
+        assert!("1,127/0,1 1 126 0".parse::<MancalaBoard>().is_ok());
+    }
+
+    #[test]
+    fn test_congkak() {
+        // the last seed landing on seeds picks them up and carries on
+        let board: MancalaBoard = "1,2,0/1,0,0 1 0 0 congkak".parse().unwrap();
+        assert_eq!(board.make_move(0).to_notation(), "0,0,1/0,1,1 2 1 0 congkak");
+        let kalah: MancalaBoard = "1,2,0/1,0,0 1 0 0".parse().unwrap();
+        assert_eq!(kalah.make_move(0).to_notation(), "0,3,0/1,0,0 2 0 0");
+        // whole games keep every seed and come to an end
+        let mut rng: StdRng = SeedableRng::seed_from_u64(7);
+        for _ in 0..20 {
+            let mut board = MancalaBoard::new(Rules::Congkak, 7, 7);
+            while board.outcome().is_none() {
+                let moves = board.legal_moves();
+                board = board.make_move(moves[rng.gen_range(0, moves.len())]);
+                assert_eq!(board.total_seeds(), 98);
+            }
+        }
+    }
+
+    #[test]
+    fn test_extra_turns() {
+        // alpha-beta, with or without a table, agrees with plain minimax
+        // in positions full of extra turns
+        let mut rng: StdRng = SeedableRng::seed_from_u64(7);
+        let mut searcher = Searcher {
+            max_depth: 4,
+            table: Some(TranspositionTable::new(1 << 20)),
+            ..Searcher::default()
+        };
+        for &rules in &[Rules::Kalah, Rules::Congkak] {
+            let mut board = MancalaBoard::new(rules, 4, 3);
+            while board.outcome().is_none() {
+                let expected = minimax(&board, 5, 0);
+                assert_eq!(analyze(board.clone(), 4).score, expected);
+                assert_eq!(searcher.search(&board).score, expected);
+                let moves = board.legal_moves();
+                board = board.make_move(moves[rng.gen_range(0, moves.len())]);
+            }
+        }
+        // player 1 wins by taking every extra turn on offer
+        let board: MancalaBoard = "0,0,2,1/2,0,0,1 1 10 8".parse().unwrap();
+        let result = analyze(board.clone(), 6);
+        assert!(result.score > WIN_SCORE / 2.0);
+        assert_eq!(result.pv, [3, 2, 3]);
+    }
+}
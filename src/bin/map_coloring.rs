@@ -3,7 +3,7 @@ use std::hash::Hash;
 use fnv::FnvHashMap;
 
 extern crate classic;
-use classic::csp::{CSP, Constraint};
+use classic::csp::{CSP, Constraint, Propagation, Solver, VariableOrder};
 
 /// Variables
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -57,20 +57,25 @@ fn main() {
     }
     //println!("variables: {:?}", variables);
     //println!("domains: {:?}", domains);
-    let mut csp: CSP<Place, Color, MapColorConstraint> = CSP::new(variables, domains);
-    csp.add_constraint(MapColorConstraint(Place::WA, Place::NT));
-    csp.add_constraint(MapColorConstraint(Place::WA, Place::SA));
-    csp.add_constraint(MapColorConstraint(Place::SA, Place::NT));
-    csp.add_constraint(MapColorConstraint(Place::Q, Place::NT));
-    csp.add_constraint(MapColorConstraint(Place::Q, Place::SA));
-    csp.add_constraint(MapColorConstraint(Place::Q, Place::NSW));
-    csp.add_constraint(MapColorConstraint(Place::NSW, Place::SA));
-    csp.add_constraint(MapColorConstraint(Place::V, Place::SA));
-    csp.add_constraint(MapColorConstraint(Place::V, Place::NSW));
-    csp.add_constraint(MapColorConstraint(Place::V, Place::T));
+    let mut csp: CSP<Place, Color, MapColorConstraint> = CSP::new(variables, domains).unwrap();
+    csp.add_constraint(MapColorConstraint(Place::WA, Place::NT)).unwrap();
+    csp.add_constraint(MapColorConstraint(Place::WA, Place::SA)).unwrap();
+    csp.add_constraint(MapColorConstraint(Place::SA, Place::NT)).unwrap();
+    csp.add_constraint(MapColorConstraint(Place::Q, Place::NT)).unwrap();
+    csp.add_constraint(MapColorConstraint(Place::Q, Place::SA)).unwrap();
+    csp.add_constraint(MapColorConstraint(Place::Q, Place::NSW)).unwrap();
+    csp.add_constraint(MapColorConstraint(Place::NSW, Place::SA)).unwrap();
+    csp.add_constraint(MapColorConstraint(Place::V, Place::SA)).unwrap();
+    csp.add_constraint(MapColorConstraint(Place::V, Place::NSW)).unwrap();
+    csp.add_constraint(MapColorConstraint(Place::V, Place::T)).unwrap();
     let mut initial_guess: FnvHashMap<Place, Color> = FnvHashMap::default();
     initial_guess.insert(Place::WA, Color::Red);
-    let solution = csp.backtracking_search(initial_guess);
+    let solver = Solver {
+        propagation: Propagation::ForwardChecking,
+        variable_order: VariableOrder::MinimumRemainingValues,
+        ..Solver::default()
+    };
+    let solution = solver.solve(&csp, initial_guess);
     if solution == None {
         println!("No solution found!");
     } else {
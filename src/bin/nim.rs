@@ -0,0 +1,165 @@
+//! multi-player nim solver
+use std::fmt;
+
+extern crate classic;
+use classic::board::Move;
+use classic::multiplayer::{MultiplayerBoard, MultiplayerSearcher, Player, Strategy};
+use text_io::read;
+
+const MAX_TAKE: u32 = 3;
+
+/// Players take turns removing 1 to 3 stones from a single pile. Whoever
+/// takes the last stone wins.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct NimBoard {
+    stones: u32,
+    players: usize,
+    turn: Player,
+}
+
+impl NimBoard {
+    fn new(stones: u32, players: usize) -> NimBoard {
+        NimBoard {
+            stones,
+            players,
+            turn: 0,
+        }
+    }
+
+    fn previous_player(&self) -> Player {
+        (self.turn + self.players - 1) % self.players
+    }
+}
+
+impl MultiplayerBoard for NimBoard {
+    fn num_players(&self) -> usize {
+        self.players
+    }
+    fn current_player(&self) -> Player {
+        self.turn
+    }
+    fn make_move(&self, location: Move) -> NimBoard {
+        NimBoard {
+            stones: self.stones - location as u32,
+            players: self.players,
+            turn: (self.turn + 1) % self.players,
+        }
+    }
+    fn legal_moves(&self) -> Vec<Move> {
+        (1..=MAX_TAKE.min(self.stones)).map(|n| n as Move).collect()
+    }
+    fn is_terminal(&self) -> bool {
+        self.stones == 0
+    }
+    fn evaluate(&self) -> Vec<f32> {
+        if self.is_terminal() {
+            let mut scores = vec![0.0; self.players];
+            scores[self.previous_player()] = 1.0;
+            scores
+        } else {
+            // no idea yet, everybody has the same chance
+            vec![1.0 / self.players as f32; self.players]
+        }
+    }
+}
+
+impl fmt::Display for NimBoard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} stones left, player {} to move", self.stones, self.turn + 1)
+    }
+}
+
+fn get_player_move(board: &NimBoard) -> Move {
+    loop {
+        let line: String = read!("{}\n");
+        if let Ok(player_move) = line.trim().parse::<Move>() {
+            if board.legal_moves().contains(&player_move) {
+                return player_move;
+            }
+        }
+        println!("Take between 1 and {} stones", MAX_TAKE.min(board.stones));
+    }
+}
+
+fn main() {
+    // main game loop
+    let mut board = NimBoard::new(21, 3);
+    // one opponent plays for itself, the other assumes everybody is out to get it
+    let computers = [
+        MultiplayerSearcher {
+            max_depth: 10,
+            strategy: Strategy::MaxN,
+        },
+        MultiplayerSearcher {
+            max_depth: 10,
+            strategy: Strategy::Paranoid,
+        },
+    ];
+    println!("Welcome to Nim! You are player 1. Take 1 to {} stones each turn; \
+              whoever takes the last stone wins.", MAX_TAKE);
+    loop {
+        println!("{}", board);
+        let next_move = match board.turn {
+            0 => get_player_move(&board),
+            p => {
+                let computer_move = computers[p - 1].search(&board).best_move.unwrap();
+                println!("Player {} takes {}", p + 1, computer_move);
+                computer_move
+            }
+        };
+        board = board.make_move(next_move);
+        if board.is_terminal() {
+            match board.previous_player() {
+                0 => println!("You win!"),
+                p => println!("Player {} wins!", p + 1),
+            }
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn searcher(strategy: Strategy) -> MultiplayerSearcher {
+        MultiplayerSearcher {
+            max_depth: 8,
+            strategy,
+        }
+    }
+
+    #[test]
+    fn test_take_last() {
+        let board = NimBoard::new(3, 3);
+        for &strategy in &[Strategy::MaxN, Strategy::Paranoid] {
+            let result = searcher(strategy).search(&board);
+            assert_eq!(result.best_move, Some(3));
+            assert_eq!(result.score, 1.0);
+            assert_eq!(result.pv, vec![3]);
+        }
+    }
+
+    #[test]
+    fn test_two_players() {
+        // with two players both reduce to minimax: leave a multiple of four
+        let board = NimBoard::new(9, 2);
+        for &strategy in &[Strategy::MaxN, Strategy::Paranoid] {
+            let result = searcher(strategy).search(&board);
+            assert_eq!(result.best_move, Some(1));
+            assert_eq!(result.score, 1.0);
+        }
+    }
+
+    #[test]
+    fn test_paranoid() {
+        // whatever player 1 takes, the other two can make sure one of them
+        // takes the last stone
+        let board = NimBoard::new(5, 3);
+        let paranoid = searcher(Strategy::Paranoid).search(&board);
+        let maxn = searcher(Strategy::MaxN).search(&board);
+        assert_eq!(paranoid.score, 0.0);
+        assert_eq!(maxn.score, 0.0);
+        assert!(paranoid.nodes < maxn.nodes);
+    }
+}
@@ -5,7 +5,7 @@ use std::env;
 use fnv::FnvHashMap;
 
 extern crate classic;
-use classic::csp::{Constraint, CSP};
+use classic::csp::{Constraint, Propagation, Solver, ValueOrder, VariableOrder, CSP};
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
 struct QueensConstraint(Vec<i8>);
@@ -39,20 +39,32 @@ fn main() {
         Some(n) => n.parse::<i8>().unwrap(),
         None => 8,
     };
+    // nqueens N --count counts the solutions instead of finding one
+    let count = args.next().as_deref() == Some("--count");
     // set up N-queens problem
     let vars: Vec<i8> = (0..n).collect();
     let mut domains: FnvHashMap<i8, Vec<i8>> = FnvHashMap::default();
     for v in vars.iter() {
         domains.insert(*v, vars.clone());
     }
-    let mut csp: CSP<i8, i8, QueensConstraint> = CSP::new(vars.clone(), domains);
+    let mut csp: CSP<i8, i8, QueensConstraint> = CSP::new(vars.clone(), domains).unwrap();
 
     // add constraints
-    csp.add_constraint(QueensConstraint(vars));
+    csp.add_constraint(QueensConstraint(vars)).unwrap();
+    let solver = Solver {
+        propagation: Propagation::ForwardChecking,
+        variable_order: VariableOrder::MinimumRemainingValues,
+        value_order: ValueOrder::LeastConstraining,
+        ..Solver::default()
+    };
+    if count {
+        println!("{} solutions", solver.count_solutions(&csp, FnvHashMap::default()));
+        return;
+    }
     // solve it
     let mut initial_guess: FnvHashMap<i8, i8> = FnvHashMap::default();
     initial_guess.insert(1, 3);
-    let solution = csp.backtracking_search(initial_guess);
+    let solution = solver.solve(&csp, initial_guess);
     if solution == None {
         println!("No solution found!");
     } else {
@@ -1,14 +1,18 @@
 //! tic-tac-toe solver
 use std::fmt;
-use rand::prelude::*;
-use rayon::prelude::*;
+use std::str::FromStr;
+use std::hash::{Hash, Hasher};
+use fnv::FnvHasher;
 
 extern crate classic;
-use classic::board::{Board, Move, Piece};
-use classic::minimax::find_best_move;
+use classic::board::{Board, Move, Notation, NotationError, Outcome, Piece};
+use classic::minimax::{find_best_move, Searcher};
+use classic::protocol::serve;
+use classic::record::{replay_file, Mode};
+use classic::tournament::{Sprt, SprtDecision, Tournament};
 use text_io::read;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 enum OwarePiece {
     P1,
     P2,
@@ -36,14 +40,35 @@ impl fmt::Display for OwarePiece {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+// Oware under the Abapa rules: sowing skips the house it started from,
+// captures of 2s and 3s run backwards along the opponent's row unless
+// they would take every seed the opponent has (a grand slam, which then
+// captures nothing), a player must give an opponent with no seeds some
+// seeds if they can, and the game ends when someone has more than half
+// the seeds, when the player to move cannot move, or when a position
+// comes round again. Seeds still on the board at the end belong to the
+// player on whose side they are.
+#[derive(Clone, Debug)]
 struct OwareBoard {
     position: [u8; 12],
     turn: OwarePiece,
     score1: u8,
     score2: u8,
+    // positions since the last capture, which are the only ones that can
+    // come round again
+    history: Vec<([u8; 12], OwarePiece)>,
 }
 
+// Boards are the same if they look the same, however they were reached
+impl PartialEq for OwareBoard {
+    fn eq(&self, other: &OwareBoard) -> bool {
+        (self.position, self.turn, self.score1, self.score2)
+            == (other.position, other.turn, other.score1, other.score2)
+    }
+}
+
+impl Eq for OwareBoard {}
+
 impl Default for OwareBoard {
     fn default() -> OwareBoard {
         OwareBoard {
@@ -51,73 +76,154 @@ impl Default for OwareBoard {
             turn: OwarePiece::P1,
             score1: 0,
             score2: 0,
+            history: Vec::new(),
         }
     }
 
 }
 
+// The houses of player
+fn side(player: OwarePiece) -> std::ops::Range<usize> {
+    match player {
+        OwarePiece::P1 => 0..6,
+        OwarePiece::P2 => 6..12,
+    }
+}
+
+impl OwareBoard {
+    fn seeds_on_side(&self, player: OwarePiece) -> u8 {
+        self.position[side(player)].iter().sum()
+    }
+
+    // Whether the position has been seen before with the same player to
+    // move
+    fn is_repetition(&self) -> bool {
+        self.history.contains(&(self.position, self.turn))
+    }
+
+    // The game ends when a player has captured more than half the seeds,
+    // a position repeats, or the player to move cannot move or feed
+    fn is_over(&self) -> bool {
+        self.score1 > 24 || self.score2 > 24 || self.is_repetition() || self.legal_moves().is_empty()
+    }
+
+    // Final scores once the game is over, with the seeds left on each side
+    // going to its owner
+    fn final_scores(&self) -> (u8, u8) {
+        if self.score1 > 24 || self.score2 > 24 {
+            (self.score1, self.score2)
+        } else {
+            (
+                self.score1 + self.seeds_on_side(OwarePiece::P1),
+                self.score2 + self.seeds_on_side(OwarePiece::P2),
+            )
+        }
+    }
+}
+
 impl Board<OwarePiece> for OwareBoard {
     fn turn(&self) -> OwarePiece {
         self.turn
     }
     fn make_move(&self, loc: Move) -> OwareBoard {
-        let n_to_sow = self.position[loc as usize] as usize; // num seeds to sow
+        let loc = loc as usize;
         let mut b = OwareBoard {
-            position: self.position.clone(),
+            position: self.position,
             turn: self.turn.opposite(),
             score1: self.score1,
             score2: self.score2,
+            history: Vec::new(),
         };
-        b.position[loc as usize] = 0;
-        for i in 0..n_to_sow { // sow
-            let h = (i + loc as usize + 1) % 12;
+        b.position[loc] = 0;
+        let mut h = loc;
+        for _ in 0..self.position[loc] { // sow, skipping the starting house
+            h = (h + 1) % 12;
+            if h == loc {
+                h = (h + 1) % 12;
+            }
             b.position[h] += 1;
         }
-        for i in (0..n_to_sow).rev() { // score
-            let h = (i + loc as usize + 1) % 12;
-            let num_before = self.position[h];
-            if num_before == 0 || num_before > 2 {
-                break;
-            }
-            if self.turn == OwarePiece::P1 && h >= 6 {
-                b.score1 += b.position[h];
-                b.position[h] = 0;
-            } else if self.turn == OwarePiece::P2 && h < 6 {
-                b.score2 += b.position[h];
-                b.position[h] = 0;
-            } else {
-                break;
+        // capture backwards from the last house while it holds 2 or 3
+        let opponent = side(self.turn.opposite());
+        let mut captured = b.position;
+        let mut seeds = 0;
+        while opponent.contains(&h) && (captured[h] == 2 || captured[h] == 3) {
+            seeds += captured[h];
+            captured[h] = 0;
+            h = (h + 11) % 12;
+        }
+        let grand_slam = captured[opponent].iter().all(|&n| n == 0);
+        if seeds > 0 && !grand_slam {
+            b.position = captured;
+            match self.turn {
+                OwarePiece::P1 => b.score1 += seeds,
+                OwarePiece::P2 => b.score2 += seeds,
             }
+        } else {
+            b.history = self.history.clone();
+            b.history.push((self.position, self.turn));
         }
         b
     }
     fn legal_moves(&self) -> Vec<Move> {
-        let mut moves = match self.turn {
-            OwarePiece::P1 => (0..6).filter(|&x| self.position[x] > 0)
-                 .map(|x| x as Move).collect::<Vec<_>>(),
-            OwarePiece::P2 => (6..12).filter(|&x| self.position[x] > 0)
-                 .map(|x| x as Move).collect::<Vec<_>>(),
-        };
-        let mut rng = rand::thread_rng(); //: StdRng = SeedableRng::seed_from_u64(0xc0ffee); //rand::thread_rng();
-        moves.shuffle(&mut rng);  // randomize the order of move searching (HashSet faster?)
+        let mut moves = side(self.turn).filter(|&x| self.position[x] > 0)
+            .map(|x| x as Move).collect::<Vec<_>>();
+        if self.seeds_on_side(self.turn.opposite()) == 0 {
+            // the opponent has to be fed if possible
+            let opponent = self.turn.opposite();
+            moves.retain(|&m| self.make_move(m).seeds_on_side(opponent) > 0);
+        }
         moves
-            // TODO: make forced move if opponent has no seeds
     }
     fn is_win(&self) -> bool {
-        // three row, three column, and then two diagonal checks
-        self.score1 > 24 || self.score2 > 24 || self.legal_moves().is_empty()
+        // decided one way or the other, see outcome
+        matches!(self.outcome(), Some(Outcome::Win) | Some(Outcome::Loss))
+    }
+    fn is_draw(&self) -> bool {
+        self.outcome() == Some(Outcome::Draw)
     }
     fn evaluate(&self, player: OwarePiece) -> f32 {
         let mut score_diff = self.score1 as f32 - self.score2 as f32;
         if player == OwarePiece::P2 {
             score_diff = -score_diff;
         }
-        if self.turn == player {
-            return -score_diff;
+        score_diff
+    }
+    fn outcome(&self) -> Option<Outcome> {
+        if !self.is_over() {
+            return None;
+        }
+        // whoever has captured more seeds wins
+        let (score1, score2) = self.final_scores();
+        let (own, other) = match self.turn {
+            OwarePiece::P1 => (score1, score2),
+            OwarePiece::P2 => (score2, score1),
+        };
+        if own > other {
+            Some(Outcome::Win)
+        } else if own < other {
+            Some(Outcome::Loss)
         } else {
-            return score_diff;
+            Some(Outcome::Draw)
         }
     }
+    fn hash_key(&self) -> Option<u64> {
+        // the history is left out: a position that repeats is scored as
+        // whatever the table has for it, which only matters in the rare
+        // endgames that go round in circles
+        let mut hasher = FnvHasher::default();
+        (self.position, self.turn, self.score1, self.score2).hash(&mut hasher);
+        Some(hasher.finish())
+    }
+    fn move_score(&self, location: Move) -> f32 {
+        // look at the biggest captures first
+        let after = self.make_move(location);
+        (after.score1 + after.score2 - self.score1 - self.score2) as f32
+    }
+    fn is_noisy(&self, location: Move) -> bool {
+        // captures
+        self.move_score(location) > 0.0
+    }
 }
 
 fn num_to_dots(n: u8) -> String {
@@ -156,70 +262,129 @@ impl fmt::Display for OwareBoard {
     }
 }
 
+// Seeds in houses 1-6 of player 1, then in houses 1-6 of player 2, the
+// player to move and the two scores: "4,4,4,4,4,4/4,4,4,4,4,4 1 0 0"
+impl Notation for OwareBoard {
+    fn to_notation(&self) -> String {
+        let houses = |side: &[u8]| side.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(",");
+        format!(
+            "{}/{} {} {} {}",
+            houses(&self.position[..6]),
+            houses(&self.position[6..]),
+            self.turn,
+            self.score1,
+            self.score2
+        )
+    }
+}
+
+impl FromStr for OwareBoard {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<OwareBoard, NotationError> {
+        let err = |reason: &str| NotationError(format!("{}: {}", reason, s));
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 4 {
+            return Err(err("expected houses, player to move and two scores"));
+        }
+        let seeds = fields[0]
+            .split(&[',', '/'][..])
+            .map(|n| n.parse::<u8>().map_err(|_| err("seeds must be numbers")))
+            .collect::<Result<Vec<_>, _>>()?;
+        if seeds.len() != 12 || fields[0].split('/').count() != 2 {
+            return Err(err("expected two rows of six houses"));
+        }
+        let mut position = [0u8; 12];
+        position.copy_from_slice(&seeds);
+        let turn = match fields[1] {
+            "1" => OwarePiece::P1,
+            "2" => OwarePiece::P2,
+            _ => return Err(err("player to move must be 1 or 2")),
+        };
+        let score1 = fields[2].parse::<u8>().map_err(|_| err("scores must be numbers"))?;
+        let score2 = fields[3].parse::<u8>().map_err(|_| err("scores must be numbers"))?;
+        let total: u32 = seeds.iter().chain(&[score1, score2]).map(|&n| n as u32).sum();
+        if total != 48 {
+            return Err(err("there must be 48 seeds in all"));
+        }
+        Ok(OwareBoard { position, turn, score1, score2, history: Vec::new() })
+    }
+}
+
 fn get_player_move<B: Board<P>, P: Piece>(board: &B) -> Move {
     loop {
         let line: String = read!("{}\n");
-        let player_move: Move = line.parse::<Move>().unwrap() - 1;
-        if board.legal_moves().contains(&player_move) {
-            return player_move;
+        // ignore anything that is not a legal move
+        if let Ok(house) = line.trim().parse::<Move>() {
+            if board.legal_moves().contains(&(house - 1)) {
+                return house - 1;
+            }
         }
     }
 }
 
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
-enum OwareResult {
-    Win1,
-    Win2,
-    Draw,
-}
+const USAGE: &str = "usage: oware [--replay FILE | --save FILE | --protocol]";
 
-impl OwareResult {
-    fn to_score(&self) -> f32 {
-        match self {
-            Self::Win1 => 1.0,
-            Self::Win2 => -1.0,
-            Self::Draw => 0.0,
+fn parse_args(args: &[String]) -> Result<Mode, String> {
+    let mut mode = Mode::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !mode.parse_option(arg, &mut args)? {
+            return Err(format!("unknown option {}", arg));
         }
     }
+    Ok(mode)
 }
 
 fn main() {
-    let ngames = 1000;
-    let mut wins1 = 0;
-    let mut wins2 = 0;
-    let mut draws = 0;
-    //let results = (0..ngames).map(|_| run_game()).collect::<Vec<OwareResult>>();
-    let results: Vec<f32> = (0..ngames).into_par_iter()
-        .map(|_| run_game().to_score())
-        .collect::<Vec<f32>>();
-    println!("{}", 0.5*(results.iter().sum::<f32>() / (ngames as f32) + 1.0));
-    //let ngames = ngames as f32;
-    //println!("wins1: {}  wins2: {}  draws: {}", wins1 as f32 / ngames, wins2 as f32 / ngames,
-     //   draws as f32 / ngames);
-}
-
-fn run_game() -> OwareResult {
-    let search_depth = 5;
-    let mut board = OwareBoard::default();
-    loop {
-        //println!("{}.", m);
-        let p1 = find_best_move(board.clone(), search_depth);
-        board = board.make_move(p1);
-        //println!("{}", board);
-        if board.is_win() {
-            return OwareResult::Win1;
-        } else if board.is_draw() {
-            return OwareResult::Draw;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mode = match parse_args(&args) {
+        Ok(mode) => mode,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
         }
-        let p2 = find_best_move(board.clone(), search_depth);
-        board = board.make_move(p2);
-        //println!("{}", board);
-        if board.is_win() {
-            return OwareResult::Win2;
-        } else if board.is_draw() {
-            return OwareResult::Draw;
+    };
+    let save_path = match mode {
+        Mode::Replay(path) => return replay_file(&path, OwareBoard::default()).expect("cannot replay games"),
+        Mode::Protocol => {
+            let stdin = std::io::stdin();
+            return serve("Oware", OwareBoard::default(), stdin.lock(), std::io::stdout())
+                .expect("protocol I/O failed");
         }
+        Mode::Play(save_path) => save_path,
+    };
+    // does following capture sequences to the end pay for itself?
+    let search_depth = 5;
+    let tournament = Tournament {
+        max_games: 1000,
+        // games that go round in circles end when a position repeats, this
+        // only stops ones that wander for too long without repeating
+        max_moves: 400,
+        sprt: Some(Sprt::new(20.0)),
+        game: "Oware".to_owned(),
+        ..Tournament::default()
+    };
+    let quiescence = |_| Searcher {
+        max_depth: search_depth,
+        quiescence_depth: 8,
+        ..Searcher::default()
+    };
+    let plain = |_| Searcher {
+        max_depth: search_depth,
+        ..Searcher::default()
+    };
+    let report = tournament.run(quiescence, plain, &[OwareBoard::default()]);
+    println!("quiescence vs plain search at depth {}: {}", search_depth, report.score);
+    match report.sprt {
+        Some(SprtDecision::AcceptH1) => println!("quiescence is stronger"),
+        Some(SprtDecision::AcceptH0) => println!("quiescence is no stronger"),
+        _ => println!("no decision"),
+    }
+    if let Some(path) = save_path {
+        let text: String = report.records.iter().map(|r| format!("{}\n", r)).collect();
+        std::fs::write(&path, text).expect("cannot save games");
     }
 }
 
@@ -236,22 +401,26 @@ fn main2() {
         let human_move = get_player_move(&board);
         board = board.make_move(human_move);
         println!("{}", board);
-        if board.is_win() {
-            println!("You win!");
-            break;
-        } else if board.is_draw() {
-            println!("Draw!");
+        match board.outcome() {
+            Some(Outcome::Loss) => println!("You win!"),
+            Some(Outcome::Win) => println!("I win!"),
+            Some(Outcome::Draw) => println!("Draw!"),
+            None => (),
+        }
+        if board.outcome().is_some() {
             break;
         }
         let computer_move = find_best_move(board.clone(), search_depth);
         println!("My move is {}", computer_move);
         board = board.make_move(computer_move);
         println!("{}", board);
-        if board.is_win() {
-            println!("I win!");
-            break;
-        } else if board.is_draw() {
-            println!("Draw!");
+        match board.outcome() {
+            Some(Outcome::Win) => println!("You win!"),
+            Some(Outcome::Loss) => println!("I win!"),
+            Some(Outcome::Draw) => println!("Draw!"),
+            None => (),
+        }
+        if board.outcome().is_some() {
             break;
         }
         turn += 1;
@@ -262,4 +431,98 @@ fn main2() {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_quiescence() {
+        // the shallow search stops in the middle of capture sequences and
+        // sees nothing to choose between the moves, following them to the
+        // end finds the move and score of a deep search
+        let board: OwareBoard = "8,8,3,1,1,10/1,1,0,8,2,2 1 3 0".parse().unwrap();
+        let mut shallow = Searcher {
+            max_depth: 1,
+            ..Searcher::default()
+        };
+        let mut quiet = Searcher {
+            max_depth: 1,
+            quiescence_depth: 8,
+            ..Searcher::default()
+        };
+        let mut deep = Searcher {
+            max_depth: 7,
+            ..Searcher::default()
+        };
+        let expected = deep.search(&board);
+        let result = quiet.search(&board);
+        assert_eq!(expected.best_move, Some(0));
+        assert_eq!(result.best_move, Some(0));
+        assert_eq!(result.score, expected.score);
+        assert!(shallow.search(&board).score < expected.score);
+        assert!(!board.legal_moves().iter().any(|&m| board.is_noisy(m)));
+    }
+
+    #[test]
+    fn test_notation() {
+        let board = OwareBoard::default();
+        assert_eq!(board.to_notation(), "4,4,4,4,4,4/4,4,4,4,4,4 1 0 0");
+        let board = board.make_move(2).make_move(11);
+        let text = board.to_notation();
+        assert_eq!(text, "5,5,1,6,5,5/5,4,4,4,4,0 1 0 0");
+        assert_eq!(text.parse(), Ok(board));
+        assert!("4,4,4,4,4,4/4,4,4,4,4,4 3 0 0".parse::<OwareBoard>().is_err());
+        assert!("4,4,4,4,4,4,4,4,4,4,4,4 1 0 0".parse::<OwareBoard>().is_err());
+        assert!("4,4,4,4,4,4/4,4,4,4,4 1 0 0".parse::<OwareBoard>().is_err());
+        assert!("4,4,4,4,4,4/4,4,4,4,4,5 1 0 0".parse::<OwareBoard>().is_err());
+    }
+
+    #[test]
+    fn test_sowing() {
+        // twelve or more seeds go round the board without refilling the
+        // house they came from
+        let board: OwareBoard = "12,1,1,1,1,1/1,1,1,1,1,1 1 12 13".parse().unwrap();
+        assert_eq!(board.make_move(0).to_notation(), "0,3,2,2,2,2/2,2,2,2,2,2 2 12 13");
+        // and captures look at what the houses hold after sowing
+        let board: OwareBoard = "0,0,0,0,0,16/0,0,0,0,0,0 1 16 16".parse().unwrap();
+        assert_eq!(board.make_move(5).to_notation(), "1,1,1,1,1,0/0,0,0,0,0,1 2 26 16");
+    }
+
+    #[test]
+    fn test_captures() {
+        // 2s and 3s are captured backwards from the last house
+        let board: OwareBoard = "0,0,0,0,0,2/1,2,0,0,1,0 1 20 22".parse().unwrap();
+        assert_eq!(board.make_move(5).to_notation(), "0,0,0,0,0,0/0,0,0,0,1,0 2 25 22");
+        assert_eq!(board.make_move(5).outcome(), Some(Outcome::Loss));
+        // but not if that would take all of the opponent's seeds
+        let board: OwareBoard = "0,0,0,0,0,2/1,2,0,0,0,0 1 21 22".parse().unwrap();
+        assert_eq!(board.make_move(5).to_notation(), "0,0,0,0,0,0/2,3,0,0,0,0 2 21 22");
+    }
+
+    #[test]
+    fn test_feeding() {
+        // a player with no seeds must be given some
+        let board: OwareBoard = "0,0,0,1,0,6/0,0,0,0,0,0 1 20 21".parse().unwrap();
+        assert_eq!(board.legal_moves(), vec![5]);
+        // and if that cannot be done the game ends, with the seeds left
+        // going to the player who has them
+        let board: OwareBoard = "1,0,0,0,0,0/0,0,0,0,0,0 1 23 24".parse().unwrap();
+        assert!(board.legal_moves().is_empty());
+        assert_eq!(board.outcome(), Some(Outcome::Draw));
+        assert!(board.is_draw() && !board.is_win());
+        let board: OwareBoard = "2,0,0,0,0,0/0,0,0,0,0,0 1 23 23".parse().unwrap();
+        assert_eq!(board.outcome(), Some(Outcome::Win));
+        assert!(board.is_win() && !board.is_draw());
+    }
+
+    #[test]
+    fn test_cycle() {
+        // two lone seeds chasing each other round the board come back to
+        // where they started, and the game ends with a seed each
+        let start: OwareBoard = "0,0,0,0,0,1/0,0,0,0,0,1 1 23 23".parse().unwrap();
+        let mut board = start.clone();
+        for &m in &[5, 11, 0, 6, 1, 7, 2, 8, 3, 9, 4, 10] {
+            assert_eq!(board.outcome(), None);
+            board = board.make_move(m);
+        }
+        assert_eq!(board, start);
+        assert_eq!(board.outcome(), Some(Outcome::Draw));
+        assert!(board.is_draw() && !board.is_win());
+    }
 }
@@ -0,0 +1,278 @@
+//! pig dice game solver
+use std::fmt;
+use rand::prelude::*;
+
+extern crate classic;
+use classic::board::{Board, ChanceBoard, Move, Piece};
+use classic::expectimax::Expectiminimax;
+use text_io::read;
+
+const GOAL: u8 = 30; // first to bank this many points wins
+const ROLL: Move = 0;
+const HOLD: Move = 1;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PigPlayer {
+    P1,
+    P2,
+}
+
+impl Piece for PigPlayer {
+    fn opposite(&self) -> PigPlayer {
+        match self {
+            PigPlayer::P1 => PigPlayer::P2,
+            PigPlayer::P2 => PigPlayer::P1,
+        }
+    }
+}
+
+impl fmt::Display for PigPlayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PigPlayer::P1 => "1",
+                PigPlayer::P2 => "2",
+            }
+        )
+    }
+}
+
+/// A player rolls as often as they like, adding each roll to the turn
+/// total, and banks the total by holding. Rolling a 1 loses the turn total
+/// and passes the die.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct PigBoard {
+    scores: [u8; 2],
+    turn_total: u8,
+    turn: PigPlayer,
+    rolling: bool, // the die is in the air: a chance node
+}
+
+impl Default for PigBoard {
+    fn default() -> PigBoard {
+        PigBoard {
+            scores: [0, 0],
+            turn_total: 0,
+            turn: PigPlayer::P1,
+            rolling: false,
+        }
+    }
+}
+
+impl PigBoard {
+    fn score(&self, player: PigPlayer) -> u8 {
+        match player {
+            PigPlayer::P1 => self.scores[0],
+            PigPlayer::P2 => self.scores[1],
+        }
+    }
+}
+
+impl Board<PigPlayer> for PigBoard {
+    fn turn(&self) -> PigPlayer {
+        self.turn
+    }
+    fn make_move(&self, location: Move) -> PigBoard {
+        let mut b = self.clone();
+        if self.rolling {
+            // location is the number rolled
+            b.rolling = false;
+            if location == 1 {
+                b.turn_total = 0;
+                b.turn = self.turn.opposite();
+            } else {
+                b.turn_total += location as u8;
+            }
+        } else if location == ROLL {
+            b.rolling = true;
+        } else {
+            let player = self.turn as usize;
+            b.scores[player] += self.turn_total;
+            b.turn_total = 0;
+            b.turn = self.turn.opposite();
+        }
+        b
+    }
+    fn legal_moves(&self) -> Vec<Move> {
+        if self.rolling {
+            (1..=6).collect()
+        } else {
+            vec![ROLL, HOLD]
+        }
+    }
+    fn is_win(&self) -> bool {
+        self.scores.iter().any(|&s| s >= GOAL)
+    }
+    fn evaluate(&self, player: PigPlayer) -> f32 {
+        // banked points, with half credit for points still at risk
+        let mut own = self.score(player) as f32;
+        let mut other = self.score(player.opposite()) as f32;
+        if self.turn == player {
+            own += 0.5 * self.turn_total as f32;
+        } else {
+            other += 0.5 * self.turn_total as f32;
+        }
+        // points at risk can run past the goal, but every evaluation has
+        // to stay strictly inside the win_score of 1.0 the engines use
+        ((own - other) / (2.0 * GOAL as f32)).clamp(-1.0 + f32::EPSILON, 1.0 - f32::EPSILON)
+    }
+}
+
+impl ChanceBoard<PigPlayer> for PigBoard {
+    fn is_chance_node(&self) -> bool {
+        self.rolling
+    }
+    fn chance_outcomes(&self) -> Vec<(Move, f32)> {
+        (1..=6).map(|roll| (roll, 1.0 / 6.0)).collect()
+    }
+}
+
+impl fmt::Display for PigBoard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Player 1: {:2}  Player 2: {:2}  (player {} has {} this turn)",
+            self.scores[0], self.scores[1], self.turn, self.turn_total
+        )
+    }
+}
+
+fn roll_die(board: PigBoard) -> PigBoard {
+    let roll = thread_rng().gen_range(1, 7);
+    println!("Rolled a {}", roll);
+    board.make_move(roll)
+}
+
+fn get_player_move() -> Move {
+    loop {
+        let line: String = read!("{}\n");
+        match line.trim() {
+            "r" => return ROLL,
+            "h" => return HOLD,
+            _ => println!("Enter r to roll or h to hold"),
+        }
+    }
+}
+
+fn main() {
+    // main game loop
+    let engine = Expectiminimax {
+        max_depth: 6,
+        win_score: 1.0,
+        ..Expectiminimax::default()
+    };
+    let mut board = PigBoard::default();
+    println!("Welcome to Pig! First to {} wins. Enter r to roll or h to hold.", GOAL);
+    loop {
+        println!("{}", board);
+        let next_move = match board.turn {
+            PigPlayer::P1 => get_player_move(),
+            PigPlayer::P2 => {
+                let computer_move = engine.search(&board).best_move.unwrap();
+                println!("I {}", if computer_move == ROLL { "roll" } else { "hold" });
+                computer_move
+            }
+        };
+        board = board.make_move(next_move);
+        if board.rolling {
+            board = roll_die(board);
+        }
+        if board.is_win() {
+            println!("{}", board);
+            if board.scores[0] >= GOAL {
+                println!("You win!");
+            } else {
+                println!("I win!");
+            }
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hold_to_win() {
+        let board = PigBoard {
+            scores: [25, 10],
+            turn_total: 5,
+            turn: PigPlayer::P1,
+            rolling: false,
+        };
+        let engine = Expectiminimax {
+            max_depth: 4,
+            win_score: 1.0,
+            ..Expectiminimax::default()
+        };
+        let result = engine.search(&board);
+        assert_eq!(result.best_move, Some(HOLD));
+        assert_eq!(result.score, 1.0);
+    }
+
+    #[test]
+    fn test_evaluation_bounds() {
+        // a long turn can put more points at risk than the goal
+        let board = PigBoard {
+            scores: [29, 0],
+            turn_total: 120,
+            turn: PigPlayer::P1,
+            rolling: false,
+        };
+        assert!(board.evaluate(PigPlayer::P1) > 0.99 && board.evaluate(PigPlayer::P1) < 1.0);
+        assert!(board.evaluate(PigPlayer::P2) < -0.99 && board.evaluate(PigPlayer::P2) > -1.0);
+        // and a lopsided position is still not scored as a won one
+        let board = PigBoard {
+            scores: [29, 0],
+            turn_total: 255,
+            turn: PigPlayer::P1,
+            rolling: true,
+        };
+        assert!(board.evaluate(PigPlayer::P1).abs() < 1.0);
+        assert!(board.evaluate(PigPlayer::P2).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_roll_when_behind() {
+        // holding 2 points cannot stop the opponent, who only needs one roll
+        let board = PigBoard {
+            scores: [5, 28],
+            turn_total: 2,
+            turn: PigPlayer::P1,
+            rolling: false,
+        };
+        let engine = Expectiminimax {
+            max_depth: 4,
+            win_score: 1.0,
+            ..Expectiminimax::default()
+        };
+        assert_eq!(engine.search(&board).best_move, Some(ROLL));
+    }
+
+    #[test]
+    fn test_star1_matches_expectiminimax() {
+        let board = PigBoard {
+            scores: [12, 15],
+            turn_total: 4,
+            turn: PigPlayer::P2,
+            rolling: false,
+        };
+        let plain = Expectiminimax {
+            max_depth: 5,
+            win_score: 1.0,
+            star1: false,
+        };
+        let pruned = Expectiminimax {
+            star1: true,
+            ..plain
+        };
+        let expected = plain.search(&board);
+        let result = pruned.search(&board);
+        assert_eq!(result.best_move, expected.best_move);
+        assert!((result.score - expected.score).abs() < 1e-5);
+        assert!(result.nodes < expected.nodes);
+    }
+}
@@ -5,7 +5,7 @@ use std::iter::FromIterator;
 use fnv::{FnvHashMap, FnvHashSet};
 
 extern crate classic;
-use classic::csp::{Constraint, CSP};
+use classic::csp::{Constraint, Propagation, Solver, CSP};
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
 struct SendMoneyConstraint(Vec<char>);
@@ -47,13 +47,17 @@ fn main() {
     for v in vars.iter() {
         domains.insert(*v, vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
     }
-    let mut csp: CSP<char, i8, SendMoneyConstraint> = CSP::new(vars.clone(), domains);
+    let mut csp: CSP<char, i8, SendMoneyConstraint> = CSP::new(vars.clone(), domains).unwrap();
     // add constraints
-    csp.add_constraint(SendMoneyConstraint(vars));
+    csp.add_constraint(SendMoneyConstraint(vars)).unwrap();
     // solve it
     let mut initial_guess: FnvHashMap<char, i8> = FnvHashMap::default();
     initial_guess.insert('M', 1);
-    let solution = csp.backtracking_search(initial_guess);
+    let solver = Solver {
+        propagation: Propagation::ArcConsistency,
+        ..Solver::default()
+    };
+    let solution = solver.solve(&csp, initial_guess);
     if solution == None {
         println!("No solution found!");
     } else {
@@ -1,9 +1,14 @@
 //! tic-tac-toe solver
 use std::fmt;
+use std::str::FromStr;
 
 extern crate classic;
-use classic::board::{Board, Move, Piece};
-use classic::minimax::find_best_move;
+use classic::board::{Board, Move, Notation, NotationError, Piece};
+use classic::minimax::Searcher;
+use classic::protocol::serve;
+use classic::record::{replay_file, GameRecord, GameResult, Mode};
+use classic::transposition::TranspositionTable;
+use std::time::Duration;
 use text_io::read;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -37,23 +42,172 @@ impl fmt::Display for TTTPiece {
     }
 }
 
+// The (column, row) steps of the four directions a line can run in, with
+// rows counted from the top
+const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (1, 1), (-1, 1)];
+
+// Size of the board and how many in a row win, the m,n,k of an m,n,k-game:
+// 3,3,3 is tic-tac-toe and 15,15,5 is gomoku
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Geometry {
+    columns: usize,
+    rows: usize,
+    k: usize,
+}
+
+impl Default for Geometry {
+    fn default() -> Geometry {
+        Geometry { columns: 3, rows: 3, k: 3 }
+    }
+}
+
+// k squares in a row, as the first square and the distance to the next
+type Segment = (usize, usize);
+
+impl Geometry {
+    fn new(columns: usize, rows: usize, k: usize) -> Result<Geometry, String> {
+        if columns == 0 || rows == 0 {
+            return Err(format!("a {}x{} board has no squares", columns, rows));
+        }
+        if k < 2 || k > columns.max(rows) {
+            return Err(format!("cannot get {} in a row on a {}x{} board", k, columns, rows));
+        }
+        Ok(Geometry { columns, rows, k })
+    }
+
+    fn squares(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    fn on_board(&self, column: isize, row: isize) -> bool {
+        column >= 0 && column < self.columns as isize && row >= 0 && row < self.rows as isize
+    }
+
+    // The segment starting at (column, row) and heading in direction, if it
+    // fits on the board
+    fn segment(&self, column: isize, row: isize, (dc, dr): (isize, isize)) -> Option<Segment> {
+        let last = self.k as isize - 1;
+        if !self.on_board(column, row) || !self.on_board(column + last * dc, row + last * dr) {
+            return None;
+        }
+        let start = row as usize * self.columns + column as usize;
+        Some((start, (dr * self.columns as isize + dc) as usize))
+    }
+
+    // Every segment that square is part of
+    fn segments_through(self, square: usize) -> impl Iterator<Item = Segment> {
+        let (column, row) = ((square % self.columns) as isize, (square / self.columns) as isize);
+        DIRECTIONS.iter().flat_map(move |&(dc, dr)| {
+            (0..self.k as isize).filter_map(move |t| self.segment(column - t * dc, row - t * dr, (dc, dr)))
+        })
+    }
+
+    // Every segment on the board
+    fn all_segments(self) -> impl Iterator<Item = Segment> {
+        (0..self.squares()).flat_map(move |square| {
+            let (column, row) = ((square % self.columns) as isize, (square / self.columns) as isize);
+            DIRECTIONS.iter().filter_map(move |&d| self.segment(column, row, d))
+        })
+    }
+}
+
+// Key of a piece on a square for Zobrist hashing, SplitMix64 of the two.
+// A position's key is the xor of the keys of its pieces, so a move updates
+// it with a single xor, and positions of any size keep 64 bits of it.
+fn zobrist(square: usize, piece: TTTPiece) -> u64 {
+    let mut z = (2 * square as u64 + (piece == TTTPiece::O) as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Xored into the key when O is to move
+const O_TO_MOVE: u64 = 0x2545_f491_4f6c_dd1d;
+
 #[derive(Clone, Debug, Eq, PartialEq)]
-struct TTTBoard {
-    position: Vec<TTTPiece>,
+struct MNKBoard {
+    geometry: Geometry,
+    position: Vec<TTTPiece>, // row by row from the top left
     turn: TTTPiece,
+    won: bool,  // someone has k in a row
+    score: i32, // sum of segment_score over all segments, kept up to date by make_move
+    key: u64,   // xor of zobrist over the pieces, kept up to date by make_move
+}
+
+impl MNKBoard {
+    // The empty board, X to move
+    fn new(geometry: Geometry) -> MNKBoard {
+        MNKBoard::from_position(geometry, vec![TTTPiece::E; geometry.squares()], TTTPiece::X)
+    }
+
+    fn from_position(geometry: Geometry, position: Vec<TTTPiece>, turn: TTTPiece) -> MNKBoard {
+        let key = position
+            .iter()
+            .enumerate()
+            .filter(|&(_, &piece)| piece != TTTPiece::E)
+            .fold(0, |key, (square, &piece)| key ^ zobrist(square, piece));
+        let mut board = MNKBoard { geometry, position, turn, won: false, score: 0, key };
+        board.won = geometry.all_segments().any(|s| board.is_line(s));
+        board.score = geometry.all_segments().map(|s| board.segment_score(s)).sum();
+        board
+    }
+
+    fn segment_squares(&self, (start, step): Segment) -> impl Iterator<Item = TTTPiece> + '_ {
+        (0..self.geometry.k).map(move |t| self.position[start + t * step])
+    }
+
+    // Numbers of X and O pieces in segment
+    fn count(&self, segment: Segment) -> (usize, usize) {
+        self.segment_squares(segment).fold((0, 0), |(x, o), piece| match piece {
+            TTTPiece::X => (x + 1, o),
+            TTTPiece::O => (x, o + 1),
+            TTTPiece::E => (x, o),
+        })
+    }
+
+    fn is_line(&self, segment: Segment) -> bool {
+        let (x, o) = self.count(segment);
+        x == self.geometry.k || o == self.geometry.k
+    }
+
+    // How good a segment is for X: segments up to three short of k in a
+    // row count, more the closer they are, mixed segments are neutral
+    fn segment_score(&self, segment: Segment) -> i32 {
+        let (x, o) = self.count(segment);
+        if x > 0 && o > 0 {
+            return 0;
+        }
+        let score = match self.geometry.k - x.max(o) {
+            0 => 10000,
+            1 => 100,
+            2 => 10,
+            3 if x + o > 0 => 1,
+            _ => 0,
+        };
+        if o > x {
+            -score
+        } else {
+            score
+        }
+    }
 }
 
-impl Board<TTTPiece> for TTTBoard {
+impl Board<TTTPiece> for MNKBoard {
     fn turn(&self) -> TTTPiece {
         self.turn
     }
-    fn make_move(&self, location: Move) -> TTTBoard {
-        let mut temp_position: Vec<TTTPiece> = self.position.clone();
-        temp_position[location as usize] = self.turn;
-        TTTBoard {
-            position: temp_position,
-            turn: self.turn.opposite(),
+    fn make_move(&self, location: Move) -> MNKBoard {
+        let square = location as usize;
+        let mut board = self.clone();
+        board.position[square] = self.turn;
+        board.key ^= zobrist(square, self.turn);
+        board.turn = self.turn.opposite();
+        // only the segments through the new piece change
+        for s in self.geometry.segments_through(square) {
+            board.score += board.segment_score(s) - self.segment_score(s);
+            board.won |= board.is_line(s);
         }
+        board
     }
     fn legal_moves(&self) -> Vec<Move> {
         (0..self.position.len())
@@ -63,80 +217,181 @@ impl Board<TTTPiece> for TTTBoard {
         //[Move(l) for l in range(self.position.len()) if self.position[l] == TTTPiece::E]
     }
     fn is_win(&self) -> bool {
-        // three row, three column, and then two diagonal checks
-        self.position[0] == self.position[1]
-            && self.position[0] == self.position[2]
-            && self.position[0] != TTTPiece::E
-            || self.position[3] == self.position[4]
-                && self.position[3] == self.position[5]
-                && self.position[3] != TTTPiece::E
-            || self.position[6] == self.position[7]
-                && self.position[6] == self.position[8]
-                && self.position[6] != TTTPiece::E
-            || self.position[0] == self.position[3]
-                && self.position[0] == self.position[6]
-                && self.position[0] != TTTPiece::E
-            || self.position[1] == self.position[4]
-                && self.position[1] == self.position[7]
-                && self.position[1] != TTTPiece::E
-            || self.position[2] == self.position[5]
-                && self.position[2] == self.position[8]
-                && self.position[2] != TTTPiece::E
-            || self.position[0] == self.position[4]
-                && self.position[0] == self.position[8]
-                && self.position[0] != TTTPiece::E
-            || self.position[2] == self.position[4]
-                && self.position[2] == self.position[6]
-                && self.position[2] != TTTPiece::E
+        self.won
     }
     fn evaluate(&self, player: TTTPiece) -> f32 {
-        if self.is_win() && self.turn == player {
-            return -1.0;
-        } else if self.is_win() && self.turn != player {
-            return 1.0;
-        } else {
-            return 0.0;
+        match player {
+            TTTPiece::O => -self.score as f32,
+            _ => self.score as f32,
         }
     }
+    fn hash_key(&self) -> Option<u64> {
+        match self.turn {
+            TTTPiece::O => Some(self.key ^ O_TO_MOVE),
+            _ => Some(self.key),
+        }
+    }
+    fn move_score(&self, location: Move) -> f32 {
+        // squares on more segments first, especially segments that are
+        // still open to one side
+        self.geometry
+            .segments_through(location as usize)
+            .map(|s| match self.count(s) {
+                (x, o) if x > 0 && o > 0 => 1,
+                (x, o) => 2 + x + o,
+            })
+            .sum::<usize>() as f32
+    }
 }
 
-impl fmt::Display for TTTBoard {
+impl fmt::Display for MNKBoard {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}|{}|{}\n-----\n{}|{}|{}\n-----\n{}|{}|{}",
-            self.position[0],
-            self.position[1],
-            self.position[2],
-            self.position[3],
-            self.position[4],
-            self.position[5],
-            self.position[6],
-            self.position[7],
-            self.position[8]
-        )
+        let rows: Vec<String> = self
+            .position
+            .chunks(self.geometry.columns)
+            .map(|row| row.iter().map(|p| p.to_string()).collect::<Vec<_>>().join("|"))
+            .collect();
+        write!(f, "{}", rows.join(&format!("\n{}\n", "-".repeat(2 * self.geometry.columns - 1))))
+    }
+}
+
+// The rows from top to bottom separated by slashes, with . for an empty
+// square, then the side to move and k if it is not 3: "XO./.X./..O X",
+// "..../..../..../.... O 4"
+impl Notation for MNKBoard {
+    fn to_notation(&self) -> String {
+        let rows: Vec<String> = self
+            .position
+            .chunks(self.geometry.columns)
+            .map(|row| row.iter().map(|&p| piece_char(p)).collect())
+            .collect();
+        let mut notation = format!("{} {}", rows.join("/"), piece_char(self.turn));
+        if self.geometry.k != 3 {
+            notation.push_str(&format!(" {}", self.geometry.k));
+        }
+        notation
+    }
+}
+
+fn piece_char(piece: TTTPiece) -> char {
+    match piece {
+        TTTPiece::X => 'X',
+        TTTPiece::O => 'O',
+        TTTPiece::E => '.',
+    }
+}
+
+impl FromStr for MNKBoard {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<MNKBoard, NotationError> {
+        let err = |reason: &str| NotationError(format!("{}: {}", reason, s));
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let (squares, turn, k) = match fields.as_slice() {
+            [squares, turn] => (squares, turn, 3),
+            [squares, turn, k] => (squares, turn, k.parse().map_err(|_| err("k must be a number"))?),
+            _ => return Err(err("expected squares, side to move and k")),
+        };
+        let rows: Vec<&str> = squares.split('/').collect();
+        let columns = rows[0].chars().count();
+        if rows.iter().any(|row| row.chars().count() != columns) {
+            return Err(err("rows must all have the same number of squares"));
+        }
+        let geometry = Geometry::new(columns, rows.len(), k).map_err(|e| err(&e))?;
+        let position = rows
+            .concat()
+            .chars()
+            .map(|c| match c {
+                'X' => Ok(TTTPiece::X),
+                'O' => Ok(TTTPiece::O),
+                '.' => Ok(TTTPiece::E),
+                _ => Err(err("squares must be X, O or .")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let turn = match *turn {
+            "X" => TTTPiece::X,
+            "O" => TTTPiece::O,
+            _ => return Err(err("side to move must be X or O")),
+        };
+        Ok(MNKBoard::from_position(geometry, position, turn))
     }
 }
 
 fn get_player_move<B: Board<P>, P: Piece>(board: &B) -> Move {
     loop {
         let line: String = read!("{}\n");
-        let player_move: Move = line.parse::<Move>().unwrap();
-        if board.legal_moves().contains(&player_move) {
-            return player_move;
+        // ignore anything that is not a legal move
+        if let Ok(player_move) = line.trim().parse::<Move>() {
+            if board.legal_moves().contains(&player_move) {
+                return player_move;
+            }
         }
     }
 }
 
+const USAGE: &str = "usage: tictactoe [--columns N] [--rows N] [--k N] [--replay FILE | --save FILE | --protocol]";
+
+// Board geometry and mode from the command line
+fn parse_args(args: &[String]) -> Result<(Geometry, Mode), String> {
+    let standard = Geometry::default();
+    let (mut columns, mut rows, mut k) = (standard.columns, standard.rows, standard.k);
+    let mut mode = Mode::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if mode.parse_option(arg, &mut args)? {
+            continue;
+        }
+        let mut value = || args.next().cloned().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--columns" | "--rows" | "--k" => {
+                let n = value()?.parse().map_err(|_| format!("{} needs a number", arg))?;
+                match arg.as_str() {
+                    "--columns" => columns = n,
+                    "--rows" => rows = n,
+                    _ => k = n,
+                }
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok((Geometry::new(columns, rows, k)?, mode))
+}
+
 fn main() {
     // main game loop
-    let mut board = TTTBoard {
-        position: (0..9).map(|_| TTTPiece::E).collect(),
-        turn: TTTPiece::X,
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (geometry, mode) = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
     };
+    let mut board = MNKBoard::new(geometry);
+    let save_path = match mode {
+        Mode::Replay(path) => return replay_file(&path, board).expect("cannot replay games"),
+        Mode::Protocol => {
+            let stdin = std::io::stdin();
+            return serve("Tic-tac-toe", board, stdin.lock(), std::io::stdout()).expect("protocol I/O failed");
+        }
+        Mode::Play(save_path) => save_path,
+    };
+    // tic-tac-toe is solved at once, bigger boards get a couple of seconds
+    // a move
+    let mut searcher = Searcher {
+        max_depth: geometry.squares() as i32,
+        time_limit: Some(Duration::from_secs(2)),
+        table: Some(TranspositionTable::new(64 << 20)),
+        ..Searcher::default()
+    };
+    let mut record = GameRecord::new("Tic-tac-toe");
+    if geometry != Geometry::default() {
+        record.set_tag("Position", board.to_notation());
+    }
     loop {
         let human_move = get_player_move(&board);
         board = board.make_move(human_move);
+        record.moves.push(human_move);
         println!("{}", board);
         if board.is_win() {
             println!("You win!");
@@ -145,9 +400,10 @@ fn main() {
             println!("Draw!");
             break;
         }
-        let computer_move = find_best_move(board.clone(), 8);
+        let computer_move = searcher.search(&board).best_move.unwrap();
         println!("My move is {}", computer_move);
         board = board.make_move(computer_move);
+        record.moves.push(computer_move);
         println!("{}", board);
         if board.is_win() {
             println!("I win!");
@@ -157,52 +413,45 @@ fn main() {
             break;
         }
     }
+    record.result = GameResult::from_board(&board, TTTPiece::X);
+    if let Some(path) = save_path {
+        std::fs::write(&path, record.to_string()).expect("cannot save game");
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use classic::minimax::find_best_move;
+    use classic::minimax::{
+        analyze, find_best_move, find_best_move_minimax, find_best_move_timed, MoveOrdering,
+        SearchStats, Searcher, WIN_SCORE,
+    };
+    use classic::mcts::Mcts;
+    use classic::protocol::serve;
+    use classic::record::RecordError;
+    use classic::tournament::{Score, Tournament};
+    use classic::transposition::TranspositionTable;
+    use std::time::Duration;
     #[test]
     fn test_easy_position() {
         // win in 1 move
-        let to_win_easy_position = vec![
-            TTTPiece::X,
-            TTTPiece::O,
-            TTTPiece::X,
-            TTTPiece::X,
-            TTTPiece::E,
-            TTTPiece::O,
-            TTTPiece::E,
-            TTTPiece::E,
-            TTTPiece::O,
-        ];
-        let test_board1: TTTBoard = TTTBoard {
-            position: to_win_easy_position,
-            turn: TTTPiece::X,
-        };
+        let test_board1: MNKBoard = "XOX/X.O/..O X".parse().unwrap();
         let answer1 = find_best_move(test_board1, 2);
         assert_eq!(answer1, 6);
     }
 
+    #[test]
+    fn test_fastest_win() {
+        // 4 also wins, but only after O replies
+        let test_board: MNKBoard = "XO./X.O/... X".parse().unwrap();
+        assert_eq!(find_best_move(test_board.clone(), 4), 6);
+        assert_eq!(find_best_move_minimax(test_board, 4), 6);
+    }
+
     #[test]
     fn test_block_position() {
         // must block O's win
-        let to_block_position = vec![
-            TTTPiece::X,
-            TTTPiece::E,
-            TTTPiece::E,
-            TTTPiece::E,
-            TTTPiece::E,
-            TTTPiece::O,
-            TTTPiece::E,
-            TTTPiece::X,
-            TTTPiece::O,
-        ];
-        let test_board2 = TTTBoard {
-            position: to_block_position,
-            turn: TTTPiece::X,
-        };
+        let test_board2: MNKBoard = "X../..O/.XO X".parse().unwrap();
         let answer2 = find_best_move(test_board2, 2);
         assert_eq!(answer2, 2);
     }
@@ -210,22 +459,299 @@ mod tests {
     #[test]
     fn test_hard_position() {
         // find the best move to win 2 moves
-        let to_win_hard_position = vec![
-            TTTPiece::X,
-            TTTPiece::E,
-            TTTPiece::E,
-            TTTPiece::E,
-            TTTPiece::E,
-            TTTPiece::O,
-            TTTPiece::O,
-            TTTPiece::X,
-            TTTPiece::E,
-        ];
-        let test_board3: TTTBoard = TTTBoard {
-            position: to_win_hard_position,
-            turn: TTTPiece::X,
-        };
+        let test_board3: MNKBoard = "X../..O/OX. X".parse().unwrap();
         let answer3 = find_best_move(test_board3, 2);
         assert_eq!(answer3, 1);
     }
+
+    #[test]
+    fn test_timed_search() {
+        // the empty board is solved well within the budget
+        let empty_board = MNKBoard::new(Geometry::default());
+        let result = find_best_move_timed(empty_board, Duration::from_secs(10));
+        assert_eq!(result.depth, 8);
+        assert_eq!(result.score, 0.0);
+        let test_board: MNKBoard = "X../..O/.XO X".parse().unwrap();
+        let result = find_best_move_timed(test_board, Duration::from_millis(100));
+        assert_eq!(result.best_move, Some(2));
+    }
+
+    #[test]
+    fn test_transposition_table() {
+        // a table shared between searches must not change the answers
+        let mut searcher = Searcher {
+            table: Some(TranspositionTable::new(1 << 20)),
+            ..Searcher::default()
+        };
+        let test_board: MNKBoard = "X../..O/OX. X".parse().unwrap();
+        // 1 and 4 both set up a fork
+        let result = searcher.search(&test_board);
+        assert!(result.best_move == Some(1) || result.best_move == Some(4));
+        assert_eq!(result.score, WIN_SCORE - 3.0);
+        assert_eq!(searcher.search(&test_board).score, WIN_SCORE - 3.0);
+        let mut board = MNKBoard::new(Geometry::default());
+        // perfect play from both sides is a draw
+        while !board.is_win() && !board.is_draw() {
+            let m = searcher.search(&board).best_move.unwrap();
+            board = board.make_move(m);
+        }
+        assert!(board.is_draw());
+    }
+
+    #[test]
+    fn test_search_result() {
+        let mut board: MNKBoard = "X../..O/OX. X".parse().unwrap();
+        let result = analyze(board.clone(), 2);
+        assert_eq!(result.best_move, Some(1));
+        assert_eq!(result.score, WIN_SCORE - 3.0); // won on the third ply
+        assert_eq!(result.depth, 2);
+        assert!(result.nodes > 0);
+        // X forks, O blocks one threat and X completes the other
+        assert_eq!(result.pv.len(), 3);
+        for &m in result.pv.iter() {
+            board = board.make_move(m);
+        }
+        assert!(board.is_win());
+
+        let drawn_board: MNKBoard = "XOX/XOO/OXX O".parse().unwrap();
+        assert_eq!(analyze(drawn_board.clone(), 2).best_move, None);
+        assert_eq!(find_best_move(drawn_board, 2), -1);
+    }
+
+    #[test]
+    fn test_parallel_search() {
+        let test_board: MNKBoard = "X../..O/... X".parse().unwrap();
+        let sequential = analyze(test_board.clone(), 6);
+        let mut searcher = Searcher {
+            max_depth: 6,
+            deterministic: true,
+            move_ordering: MoveOrdering::NONE,
+            ..Searcher::default()
+        };
+        let parallel = searcher.search_parallel(&test_board);
+        assert_eq!(parallel.best_move, sequential.best_move);
+        assert_eq!(parallel.score, sequential.score);
+        assert_eq!(parallel.pv, sequential.pv);
+        // without determinism a different but equally good move may be chosen
+        searcher.deterministic = false;
+        assert_eq!(searcher.search_parallel(&test_board).score, sequential.score);
+    }
+
+    #[test]
+    fn test_mcts() {
+        let test_board: MNKBoard = "X../..O/.XO X".parse().unwrap();
+        let mut mcts = Mcts::new(42);
+        mcts.iterations = Some(2000);
+        let result = mcts.search(&test_board);
+        assert_eq!(result.best_move, Some(2));
+        assert_eq!(result.nodes, 2000);
+        assert_eq!(result.pv[0], 2);
+        // the same seed gives the same search
+        let mut again = Mcts::new(42);
+        again.iterations = Some(2000);
+        assert_eq!(again.search(&test_board), result);
+    }
+
+    #[test]
+    fn test_move_ordering() {
+        // ordering only changes how much work it takes, not the outcome
+        let board = MNKBoard::new(Geometry::default());
+        let mut plain = Searcher {
+            max_depth: 8,
+            move_ordering: MoveOrdering::NONE,
+            ..Searcher::default()
+        };
+        let mut ordered = Searcher {
+            max_depth: 8,
+            move_ordering: MoveOrdering::ALL,
+            ..Searcher::default()
+        };
+        let plain_result = plain.search(&board);
+        let ordered_result = ordered.search(&board);
+        assert_eq!(ordered_result.score, plain_result.score);
+        assert!(ordered_result.nodes < plain_result.nodes);
+        assert_eq!(ordered_result.best_move, Some(4));
+    }
+
+    #[test]
+    fn test_search_stats() {
+        let board = MNKBoard::new(Geometry::default());
+        let mut searcher = Searcher {
+            max_depth: 4,
+            ..Searcher::default()
+        };
+        let mut stats = SearchStats::default();
+        let result = searcher.search_with_observer(&board, &mut stats);
+        assert_eq!(stats.nodes, result.nodes);
+        assert_eq!(stats.depth, 4);
+        assert_eq!(stats.nodes_per_iteration.len(), 4);
+        assert_eq!(stats.nodes_per_iteration.iter().sum::<u64>(), stats.nodes);
+        assert_eq!(stats.nodes_per_ply[1..].iter().sum::<u64>(), stats.nodes);
+        assert!(stats.leaf_evals > 0 && stats.leaf_evals < stats.nodes);
+        assert!(stats.first_move_cutoffs <= stats.cutoffs());
+        assert!(stats.branching_factor().unwrap() > 1.0);
+        // watching the search does not change it
+        assert_eq!(searcher.search(&board), result);
+    }
+
+    #[test]
+    fn test_game_record() {
+        let empty = MNKBoard::new(Geometry::default());
+        let mut record: GameRecord = "[Game \"Tic-tac-toe\"]\n\n1. 4 0 2. 2 6 3. 3 5 4. 1 7 5. 8 1/2-1/2\n"
+            .parse()
+            .unwrap();
+        let board = record.final_position(empty.clone()).unwrap();
+        assert!(board.is_draw());
+        assert_eq!(GameResult::from_board(&board, TTTPiece::X), record.result);
+        assert_eq!(record.replay(empty.clone()).count(), 9);
+
+        record.moves = vec![4, 0, 4];
+        let positions: Vec<_> = record.replay(empty.clone()).collect();
+        assert_eq!(positions.len(), 3);
+        assert_eq!(positions[2], Err(RecordError::IllegalMove { ply: 2, location: 4 }));
+
+        record.moves = vec![0, 3, 1, 4, 2];
+        let board = record.final_position(empty).unwrap();
+        assert_eq!(GameResult::from_board(&board, TTTPiece::X), GameResult::FirstWins);
+    }
+
+    #[test]
+    fn test_notation() {
+        let board = MNKBoard::new(Geometry::default()).make_move(0).make_move(1).make_move(4).make_move(8);
+        assert_eq!(board.to_notation(), "XO./.X./..O X");
+        assert_eq!("XO./.X./..O X".parse(), Ok(board));
+        assert!("XO./.X./..O".parse::<MNKBoard>().is_err());
+        assert!("XO./.X../..O X".parse::<MNKBoard>().is_err());
+        assert!("XO./.X./..Q X".parse::<MNKBoard>().is_err());
+        assert!("XO./.X./..O E".parse::<MNKBoard>().is_err());
+        assert!("XO./.X./..O X 4".parse::<MNKBoard>().is_err());
+        let board: MNKBoard = "..../..../..../.... O 4".parse().unwrap();
+        assert_eq!(board.geometry, Geometry::new(4, 4, 4).unwrap());
+        assert_eq!(board.to_notation(), "..../..../..../.... O 4");
+    }
+
+    #[test]
+    fn test_incremental_score() {
+        // incremental scores and wins agree with checking every segment
+        // from scratch
+        use rand::prelude::*;
+        let mut rng: StdRng = SeedableRng::seed_from_u64(7);
+        for &(columns, rows, k) in &[(3, 3, 3), (4, 4, 4), (7, 5, 4), (9, 9, 5)] {
+            for _ in 0..20 {
+                let mut board = MNKBoard::new(Geometry::new(columns, rows, k).unwrap());
+                while !board.is_win() && !board.is_draw() {
+                    let moves = board.legal_moves();
+                    board = board.make_move(moves[rng.gen_range(0, moves.len())]);
+                    let fresh = MNKBoard::from_position(board.geometry, board.position.clone(), board.turn);
+                    assert_eq!(board, fresh);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bigger_boards() {
+        // O has to take the last square of X's row
+        let board: MNKBoard = "XXX./O.../O.../.... O 4".parse().unwrap();
+        assert_eq!(find_best_move(board.clone(), 2), 3);
+        assert!(board.evaluate(TTTPiece::X) > 0.0);
+        assert_eq!(board.make_move(15).outcome(), None);
+        assert!(board.make_move(15).make_move(3).is_win());
+
+        // an open four in gomoku cannot be stopped
+        let gomoku = Geometry::new(15, 15, 5).unwrap();
+        let mut board = MNKBoard::new(gomoku);
+        for &m in &[110, 0, 111, 2, 112, 224, 113] {
+            board = board.make_move(m);
+        }
+        let mut searcher = Searcher {
+            max_depth: 2,
+            ..Searcher::default()
+        };
+        assert_eq!(searcher.search(&board).score, -(WIN_SCORE - 2.0));
+        let result = searcher.search(&board.make_move(4));
+        assert!(result.best_move == Some(109) || result.best_move == Some(114));
+        assert_eq!(result.score, WIN_SCORE - 1.0);
+
+        // every position with a piece each has its own hash key, even with
+        // far more squares than fit in a base 3 key
+        let empty = MNKBoard::new(gomoku);
+        let mut keys = std::collections::HashSet::new();
+        for x in 0..225 {
+            let board = empty.make_move(x);
+            assert!(keys.insert(board.hash_key().unwrap()));
+            for o in (0..225).filter(|&o| o != x) {
+                assert!(keys.insert(board.make_move(o).hash_key().unwrap()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_tournament() {
+        let empty: MNKBoard = ".../.../... X".parse().unwrap();
+        let tournament = Tournament {
+            max_games: 10,
+            ..Tournament::default()
+        };
+        // perfect play only ever draws against itself
+        let perfect = |_| Searcher::default();
+        let report = tournament.run(perfect, perfect, std::slice::from_ref(&empty));
+        assert_eq!(report.score, Score { wins: 0, draws: 10, losses: 0 });
+        assert_eq!(report.records.len(), 10);
+        assert_eq!(report.records[1].tag("First"), Some("B"));
+        // and never loses against an engine playing the first free square
+        let naive = |_| |board: &MNKBoard| board.legal_moves().first().cloned();
+        let report = tournament.run(perfect, naive, &[empty]);
+        assert_eq!(report.score.losses, 0);
+        assert!(report.score.wins > 0);
+        for record in &report.records {
+            assert_ne!(record.result, GameResult::Unfinished);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one opening")]
+    fn test_tournament_without_openings() {
+        let perfect = |_| Searcher::default();
+        let openings: [MNKBoard; 0] = [];
+        Tournament::default().run(perfect, perfect, &openings);
+    }
+
+    #[test]
+    fn test_protocol() {
+        let empty: MNKBoard = ".../.../... X".parse().unwrap();
+        let commands = "protocol\n\
+                        position notation X../..O/.XO X\n\
+                        go depth 2\n\
+                        move 2\n\
+                        move 2\n\
+                        move x\n\
+                        show\n\
+                        position startpos moves 0 3 1 4 2\n\
+                        go\n\
+                        go deep\n\
+                        go depth 3000000000\n\
+                        go depth -1\n\
+                        quit\n\
+                        isready\n";
+        let mut output = Vec::new();
+        serve("Tic-tac-toe", empty, commands.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[..2], ["id name Tic-tac-toe", "protocolok"]);
+        assert!(lines[2].starts_with("info depth 1 "));
+        assert_eq!(lines[4], "bestmove 2");
+        assert_eq!(
+            lines[5..],
+            [
+                "error illegal move 2",
+                "error malformed move x",
+                "notation X.X/..O/.XO O",
+                "bestmove none",
+                "error expected go [depth <plies>] [movetime <ms>]",
+                "error depth must be at most 2147483647",
+                "error expected go [depth <plies>] [movetime <ms>]",
+            ]
+        );
+    }
 }
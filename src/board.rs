@@ -1,7 +1,15 @@
 
 pub type Move = i32;
 
-pub trait Piece : Clone + Copy {
+// Result of a finished game for the player whose turn it is
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Win,
+    Loss,
+    Draw,
+}
+
+pub trait Piece : Clone + Copy + PartialEq {
     fn opposite(&self) -> Self;
 }
 
@@ -14,4 +22,62 @@ pub trait Board<P: Piece> : std::fmt::Display {
         !self.is_win() && self.legal_moves().len() == 0
     }
     fn evaluate(&self, player: P) -> f32;
+    // None while the game is still going. By default a won position is a
+    // loss for the side to move, as the player who just moved made the win.
+    fn outcome(&self) -> Option<Outcome> {
+        if self.is_win() {
+            Some(Outcome::Loss)
+        } else if self.is_draw() {
+            Some(Outcome::Draw)
+        } else {
+            None
+        }
+    }
+    // Key identifying the position for the transposition table. It must
+    // cover everything that affects play from here, including the side to
+    // move. Boards that return None are never cached.
+    fn hash_key(&self) -> Option<u64> {
+        None
+    }
+    // How promising a move looks before searching it, higher is better.
+    // Searcher tries likely good moves (captures, central squares, ...)
+    // first, which lets alpha-beta prune more.
+    fn move_score(&self, _location: Move) -> f32 {
+        0.0
+    }
+    // Whether a move changes the position so much (a capture, say) that
+    // evaluate is unreliable until the dust settles. Searcher keeps
+    // following noisy moves past its depth limit if quiescence is enabled.
+    fn is_noisy(&self, _location: Move) -> bool {
+        false
+    }
+}
+
+// A board for games of chance. At a chance node nobody chooses: the next
+// position is decided by dice, cards, ... and legal_moves and make_move
+// work with the possible results instead of real moves.
+pub trait ChanceBoard<P: Piece> : Board<P> {
+    fn is_chance_node(&self) -> bool;
+    // The results that can happen at a chance node, as moves to pass to
+    // make_move, with their probabilities, which add up to 1
+    fn chance_outcomes(&self) -> Vec<(Move, f32)>;
 }
+
+// A compact one-line text form of a position, like FEN in chess, for
+// writing down test positions and swapping them with other programs.
+// Parsing it back with FromStr gives the same board.
+pub trait Notation : std::str::FromStr<Err = NotationError> {
+    fn to_notation(&self) -> String;
+}
+
+// Text that does not describe a valid position, and what is wrong with it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NotationError(pub String);
+
+impl std::fmt::Display for NotationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid position: {}", self.0)
+    }
+}
+
+impl std::error::Error for NotationError {}
@@ -1,8 +1,17 @@
 //! Constraint Satisfaction Problems
-//! Template types plus backtracking solver
+//! Template types plus backtracking solver, optionally pruning domains by
+//! forward checking or maintaining arc consistency as it goes, and choosing
+//! which variable to assign next and which value to try first by the usual
+//! heuristics. The search keeps its own stack, and can be bounded by a node
+//! or time budget or cancelled from another thread.
 
 //use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
 use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use fnv::FnvHashMap;
 
 pub trait Constraint<V: Eq + Hash, D> {
@@ -14,74 +23,830 @@ pub trait Constraint<V: Eq + Hash, D> {
 pub struct CSP<V, D, C>
 where
     V: Clone + Copy + Eq + Hash,
-    D: Clone + Copy,
+    D: Clone + Copy + PartialEq,
     C: Clone + Constraint<V, D>,
 {
     variables: Vec<V>,
     domains: FnvHashMap<V, Vec<D>>,
-    constraints: FnvHashMap<V, Vec<C>>,
+    constraints: Vec<C>,
+    // indices into constraints of the constraints on each variable
+    variable_constraints: FnvHashMap<V, Vec<usize>>,
 }
 
-impl<V: Clone + Copy + Eq + Hash, D: Clone + Copy, C: Clone + Constraint<V, D>> CSP<V, D, C> {
-    pub fn new(variables: Vec<V>, domains: FnvHashMap<V, Vec<D>>) -> CSP<V, D, C> {
-        let mut constraints: FnvHashMap<V, Vec<C>> = FnvHashMap::default();
-        for variable in &variables {
-            constraints.insert(*variable, Vec::new());
-            if !domains.contains_key(&variable) {
-                panic!("Every variable should have a domain assigned to it.");
+// The constraint (as an index into CSP::constraints) that could not be
+// satisfied
+type Conflict = usize;
+
+// Domains as they were before the search narrowed them, most recent last,
+// so that backtracking can put them back instead of copying every domain
+// at every step
+type Trail<V, D> = Vec<(V, Vec<D>)>;
+
+// What is wrong with the definition of a CSP
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CspError<V> {
+    // a variable listed more than once
+    DuplicateVariable(V),
+    // a variable without a domain
+    MissingDomain(V),
+    // a variable whose domain has no values, so the CSP has no solution
+    EmptyDomain(V),
+    // a constraint or assignment on something that is not a variable of
+    // the CSP
+    UnknownVariable(V),
+}
+
+impl<V: fmt::Debug> fmt::Display for CspError<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CspError::DuplicateVariable(v) => write!(f, "variable {:?} is listed twice", v),
+            CspError::MissingDomain(v) => write!(f, "variable {:?} has no domain", v),
+            CspError::EmptyDomain(v) => write!(f, "variable {:?} has an empty domain", v),
+            CspError::UnknownVariable(v) => write!(f, "{:?} is not a variable", v),
+        }
+    }
+}
+
+impl<V: fmt::Debug> std::error::Error for CspError<V> {}
+
+impl<V: Clone + Copy + Eq + Hash, D: Clone + Copy + PartialEq, C: Clone + Constraint<V, D>> CSP<V, D, C> {
+    // Every variable needs a non-empty domain. Domains of anything else are
+    // ignored.
+    pub fn new(variables: Vec<V>, domains: FnvHashMap<V, Vec<D>>) -> Result<CSP<V, D, C>, CspError<V>> {
+        let mut variable_constraints: FnvHashMap<V, Vec<usize>> = FnvHashMap::default();
+        for &variable in &variables {
+            if variable_constraints.insert(variable, Vec::new()).is_some() {
+                return Err(CspError::DuplicateVariable(variable));
+            }
+            match domains.get(&variable) {
+                None => return Err(CspError::MissingDomain(variable)),
+                Some(domain) if domain.is_empty() => return Err(CspError::EmptyDomain(variable)),
+                Some(_) => (),
             }
         }
-        CSP {
+        Ok(CSP {
             variables,
             domains,
-            constraints,
-        }
+            constraints: Vec::new(),
+            variable_constraints,
+        })
     }
-    pub fn add_constraint(&mut self, constraint: C) {
+    // Fails, leaving the CSP as it was, if the constraint is on anything
+    // that is not a variable
+    pub fn add_constraint(&mut self, constraint: C) -> Result<(), CspError<V>> {
         let vars = constraint.variables();
+        if let Some(&unknown) = vars.iter().find(|v| !self.variable_constraints.contains_key(v)) {
+            return Err(CspError::UnknownVariable(unknown));
+        }
+        let index = self.constraints.len();
         for variable in vars {
-            if !self.variables.contains(&variable) {
-                panic!("Variable in constraint not in CSP");
-            } else {
-                self.constraints
-                    .get_mut(&variable)
-                    .unwrap()
-                    .push(constraint.clone())
+            if let Some(constraints) = self.variable_constraints.get_mut(&variable) {
+                // once per variable, even if the constraint lists it twice
+                if constraints.last() != Some(&index) {
+                    constraints.push(index);
+                }
             }
         }
+        self.constraints.push(constraint);
+        Ok(())
+    }
+    // An assignment can only be solved from if it is to variables of the
+    // CSP
+    pub fn check_assignment(&self, assignment: &FnvHashMap<V, D>) -> Result<(), CspError<V>> {
+        match assignment.keys().find(|v| !self.variable_constraints.contains_key(v)) {
+            Some(&unknown) => Err(CspError::UnknownVariable(unknown)),
+            None => Ok(()),
+        }
+    }
+    // The first constraint on variable that the assignment breaks
+    fn conflict(&self, variable: V, assignment: &FnvHashMap<V, D>) -> Option<Conflict> {
+        self.variable_constraints
+            .get(&variable)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .find(|&c| !self.constraints[c].satisfied(assignment))
     }
     fn consistent(&self, variable: V, assignment: &FnvHashMap<V, D>) -> bool {
-        let constraints = self.constraints.get(&variable).unwrap();
-        for c in constraints {
-            if !c.satisfied(assignment) {
-                return false;
+        self.conflict(variable, assignment).is_none()
+    }
+    pub fn backtracking_search(&self, assignment: FnvHashMap<V, D>) -> Option<FnvHashMap<V, D>> {
+        Solver::default().solve(self, assignment)
+    }
+
+    // The variables that share a constraint with variable
+    fn neighbours(&self, variable: V) -> Vec<V> {
+        let mut neighbours = Vec::new();
+        for &c in &self.variable_constraints[&variable] {
+            for v in self.constraints[c].variables() {
+                if v != variable && !neighbours.contains(&v) {
+                    neighbours.push(v);
+                }
             }
         }
-        true
-        //.iter()
-        //.any(|&c| !*c.satisfied(*assignment))
+        neighbours
     }
-    pub fn backtracking_search(&self, assignment: FnvHashMap<V, D>) -> Option<FnvHashMap<V, D>> {
-        if assignment.len() == self.variables.len() {
-            return Some(assignment);
+
+    // Remove the values of variable's unassigned neighbours that conflict
+    // with the assignment, failing with the constraint that removed the
+    // last value of some neighbour
+    fn forward_check(
+        &self,
+        variable: V,
+        assignment: &mut FnvHashMap<V, D>,
+        domains: &mut FnvHashMap<V, Vec<D>>,
+        trail: &mut Trail<V, D>,
+    ) -> Result<(), Conflict> {
+        for neighbour in self.neighbours(variable) {
+            if assignment.contains_key(&neighbour) {
+                continue;
+            }
+            let domain = &domains[&neighbour];
+            let mut last_conflict = None;
+            let kept: Vec<D> = domain
+                .iter()
+                .cloned()
+                .filter(|&value| {
+                    assignment.insert(neighbour, value);
+                    let conflict = self.conflict(neighbour, assignment);
+                    assignment.remove(&neighbour);
+                    last_conflict = conflict.or(last_conflict);
+                    conflict.is_none()
+                })
+                .collect();
+            if kept.len() < domain.len() {
+                let empty = kept.is_empty();
+                trail.push((neighbour, domains.insert(neighbour, kept).unwrap()));
+                if empty {
+                    return Err(last_conflict.unwrap());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // How many values of variable's unassigned neighbours would be ruled
+    // out by giving it value, for least constraining value ordering
+    fn values_ruled_out(&self, variable: V, value: D, assignment: &mut FnvHashMap<V, D>, domains: &FnvHashMap<V, Vec<D>>) -> usize {
+        assignment.insert(variable, value);
+        let mut ruled_out = 0;
+        for neighbour in self.neighbours(variable) {
+            if assignment.contains_key(&neighbour) {
+                continue;
+            }
+            for &other in &domains[&neighbour] {
+                assignment.insert(neighbour, other);
+                ruled_out += !self.consistent(neighbour, assignment) as usize;
+                assignment.remove(&neighbour);
+            }
+        }
+        assignment.remove(&variable);
+        ruled_out
+    }
+
+    // Remove the values of x that no value of y goes with, given the
+    // assignment. Ok(true) if any were removed, the constraint that ruled
+    // out the last one if none are left.
+    fn revise(
+        &self,
+        x: V,
+        y: V,
+        assignment: &mut FnvHashMap<V, D>,
+        domains: &mut FnvHashMap<V, Vec<D>>,
+        trail: &mut Trail<V, D>,
+    ) -> Result<bool, Conflict> {
+        let (before, y_domain) = (domains.remove(&x).unwrap(), &domains[&y]);
+        let y_value = assignment.get(&y).cloned();
+        let mut last_conflict = None;
+        let x_domain: Vec<D> = before.iter().cloned().filter(|&a| {
+            assignment.insert(x, a);
+            let conflict = match y_value {
+                Some(_) => self.conflict(x, assignment),
+                None => {
+                    let mut conflict = None;
+                    for &b in y_domain {
+                        assignment.insert(y, b);
+                        conflict = self.conflict(x, assignment);
+                        assignment.remove(&y);
+                        if conflict.is_none() {
+                            break;
+                        }
+                    }
+                    conflict
+                }
+            };
+            assignment.remove(&x);
+            last_conflict = conflict.or(last_conflict);
+            conflict.is_none()
+        }).collect();
+        let revised = x_domain.len() < before.len();
+        let empty = x_domain.is_empty();
+        if revised {
+            trail.push((x, before));
+            domains.insert(x, x_domain);
+        } else {
+            domains.insert(x, before);
+        }
+        match last_conflict {
+            Some(conflict) if empty => Err(conflict),
+            _ => Ok(revised),
+        }
+    }
+
+    // AC-3 over the unassigned variables, starting from the arcs in queue,
+    // so that every value left has a supporting value in the domain of
+    // each neighbour. Fails with the constraint that emptied a domain.
+    fn ac3(
+        &self,
+        mut queue: VecDeque<(V, V)>,
+        assignment: &mut FnvHashMap<V, D>,
+        domains: &mut FnvHashMap<V, Vec<D>>,
+        trail: &mut Trail<V, D>,
+    ) -> Result<(), Conflict> {
+        while let Some((x, y)) = queue.pop_front() {
+            if assignment.contains_key(&x) || !self.revise(x, y, assignment, domains, trail)? {
+                continue;
+            }
+            for z in self.neighbours(x) {
+                if z != y && !assignment.contains_key(&z) {
+                    queue.push_back((z, x));
+                }
+            }
         }
-        let unassigned: Vec<V> = self
-            .variables
-            .clone()
+        Ok(())
+    }
+
+    // Every arc into variable from its unassigned neighbours
+    fn arcs_to(&self, variable: V, assignment: &FnvHashMap<V, D>) -> VecDeque<(V, V)> {
+        self.neighbours(variable)
             .into_iter()
-            .filter(|&v| !assignment.contains_key(&v))
-            .collect();
-        let first = unassigned[0];
-        for value in &self.domains[&first] {
-            let mut local_assignment = assignment.clone();
-            local_assignment.insert(first, *value);
-            if self.consistent(first, &local_assignment) {
-                if let Some(result) = self.backtracking_search(local_assignment) {
-                    return Some(result);
+            .filter(|n| !assignment.contains_key(n))
+            .map(|n| (n, variable))
+            .collect()
+    }
+
+    // Apply propagation after variable has been assigned
+    fn propagate(
+        &self,
+        propagation: Propagation,
+        variable: V,
+        assignment: &mut FnvHashMap<V, D>,
+        domains: &mut FnvHashMap<V, Vec<D>>,
+        trail: &mut Trail<V, D>,
+    ) -> Result<(), Conflict> {
+        match propagation {
+            Propagation::Backtracking => Ok(()),
+            Propagation::ForwardChecking => self.forward_check(variable, assignment, domains, trail),
+            Propagation::ArcConsistency => self.ac3(self.arcs_to(variable, assignment), assignment, domains, trail),
+        }
+    }
+}
+
+// How much the solver prunes the domains of unassigned variables after
+// each assignment
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Propagation {
+    // none, constraints are only checked as their variables are assigned
+    Backtracking,
+    // remove the values of neighbours of the assigned variable that
+    // conflict with it
+    ForwardChecking,
+    // keep every arc between unassigned variables consistent with AC-3
+    // (MAC), starting with all of them before the search
+    ArcConsistency,
+}
+
+// Which unassigned variable the solver assigns next
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VariableOrder {
+    // the order the variables were given in
+    Declaration,
+    // the one on constraints with the most unassigned variables
+    Degree,
+    // the one with the fewest values left (MRV), breaking ties by degree
+    MinimumRemainingValues,
+    // the one with the lowest ratio of values left to the weights of its
+    // constraints, where a constraint's weight counts the times it caused
+    // a dead end (dom/wdeg)
+    DomainOverWeightedDegree,
+}
+
+// Which value of the chosen variable the solver tries first
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ValueOrder {
+    // the order of the domain
+    Domain,
+    // the one that rules out the fewest values of unassigned neighbours
+    // (LCV)
+    LeastConstraining,
+}
+
+// Backtracking search for a CSP, configured per solve
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Solver {
+    pub propagation: Propagation,
+    pub variable_order: VariableOrder,
+    pub value_order: ValueOrder,
+    // give up after trying this many values, or after this long
+    pub node_limit: Option<u64>,
+    pub time_limit: Option<Duration>,
+}
+
+impl Default for Solver {
+    fn default() -> Solver {
+        Solver {
+            propagation: Propagation::Backtracking,
+            variable_order: VariableOrder::Declaration,
+            value_order: ValueOrder::Domain,
+            node_limit: None,
+            time_limit: None,
+        }
+    }
+}
+
+// Why a search stopped before it could tell whether there are any (more)
+// solutions
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GaveUp {
+    NodeLimit,
+    TimeLimit,
+    Cancelled,
+}
+
+// Why try_solve or try_count_solutions has no answer
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SolveError<V> {
+    // the assignment to start from is not to variables of the CSP
+    Invalid(CspError<V>),
+    GaveUp(GaveUp),
+}
+
+impl<V> From<CspError<V>> for SolveError<V> {
+    fn from(e: CspError<V>) -> SolveError<V> {
+        SolveError::Invalid(e)
+    }
+}
+
+impl<V> From<GaveUp> for SolveError<V> {
+    fn from(reason: GaveUp) -> SolveError<V> {
+        SolveError::GaveUp(reason)
+    }
+}
+
+impl<V: fmt::Debug> fmt::Display for SolveError<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolveError::Invalid(e) => write!(f, "{}", e),
+            SolveError::GaveUp(reason) => write!(f, "gave up: {:?}", reason),
+        }
+    }
+}
+
+impl<V: fmt::Debug> std::error::Error for SolveError<V> {}
+
+// Stops a search from another thread, see Solutions::cancel_with. Clones
+// share the flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// A variable being tried with each of its values in turn
+struct Frame<V, D> {
+    variable: V,
+    values: std::vec::IntoIter<D>,
+    trail: usize, // length of the trail before the variable was assigned
+}
+
+impl Solver {
+    // A complete assignment satisfying every constraint that extends
+    // assignment, if there is one. None also when a limit ran out first or
+    // the assignment is to something that is not a variable, use try_solve
+    // to tell these apart.
+    pub fn solve<V, D, C>(&self, csp: &CSP<V, D, C>, assignment: FnvHashMap<V, D>) -> Option<FnvHashMap<V, D>>
+    where
+        V: Clone + Copy + Eq + Hash,
+        D: Clone + Copy + PartialEq,
+        C: Clone + Constraint<V, D>,
+    {
+        self.solutions(csp, assignment).ok()?.next()
+    }
+
+    // Like solve, but Ok(None) means there is no solution and Err that the
+    // assignment was invalid or the search gave up before finding out
+    pub fn try_solve<V, D, C>(
+        &self,
+        csp: &CSP<V, D, C>,
+        assignment: FnvHashMap<V, D>,
+    ) -> Result<Option<FnvHashMap<V, D>>, SolveError<V>>
+    where
+        V: Clone + Copy + Eq + Hash,
+        D: Clone + Copy + PartialEq,
+        C: Clone + Constraint<V, D>,
+    {
+        Ok(self.solutions(csp, assignment)?.try_next()?)
+    }
+
+    // Every complete assignment satisfying every constraint that extends
+    // assignment, found one at a time as the iterator is advanced. Fails if
+    // the assignment is to something that is not a variable.
+    pub fn solutions<'a, V, D, C>(
+        &self,
+        csp: &'a CSP<V, D, C>,
+        assignment: FnvHashMap<V, D>,
+    ) -> Result<Solutions<'a, V, D, C>, CspError<V>>
+    where
+        V: Clone + Copy + Eq + Hash,
+        D: Clone + Copy + PartialEq,
+        C: Clone + Constraint<V, D>,
+    {
+        csp.check_assignment(&assignment)?;
+        let mut assignment = assignment;
+        let mut domains = csp.domains.clone();
+        for (variable, &value) in &assignment {
+            domains.insert(*variable, vec![value]);
+        }
+        // never undone, the trail only matters below the root
+        let mut trail = Vec::new();
+        let consistent = match self.propagation {
+            Propagation::Backtracking => Ok(()),
+            Propagation::ForwardChecking => {
+                let assigned: Vec<V> = assignment.keys().cloned().collect();
+                assigned
+                    .into_iter()
+                    .try_for_each(|v| csp.forward_check(v, &mut assignment, &mut domains, &mut trail))
+            }
+            Propagation::ArcConsistency => {
+                let arcs = csp.variables.iter().flat_map(|&v| csp.arcs_to(v, &assignment)).collect();
+                csp.ac3(arcs, &mut assignment, &mut domains, &mut trail)
+            }
+        };
+        trail.clear();
+        // the seed itself has to be allowed by the domains and constraints,
+        // propagation only looks at the unassigned variables
+        let allowed = assignment
+            .iter()
+            .all(|(variable, value)| csp.domains[variable].contains(value) && csp.consistent(*variable, &assignment));
+        Ok(Solutions {
+            solver: *self,
+            csp,
+            assignment,
+            domains,
+            trail,
+            stack: Vec::new(),
+            at_root: allowed && consistent.is_ok(),
+            weights: vec![1; csp.constraints.len()],
+            symmetry: None,
+            cancel: None,
+            deadline: self.time_limit.map(|limit| Instant::now() + limit),
+            nodes: 0,
+            gave_up: None,
+        })
+    }
+
+    // How many solutions extend assignment, 0 if it is to something that
+    // is not a variable
+    pub fn count_solutions<V, D, C>(&self, csp: &CSP<V, D, C>, assignment: FnvHashMap<V, D>) -> usize
+    where
+        V: Clone + Copy + Eq + Hash,
+        D: Clone + Copy + PartialEq,
+        C: Clone + Constraint<V, D>,
+    {
+        self.solutions(csp, assignment).map_or(0, Iterator::count)
+    }
+
+    // Like count_solutions, but fails if the assignment was invalid or the
+    // search gave up before it had seen them all
+    pub fn try_count_solutions<V, D, C>(
+        &self,
+        csp: &CSP<V, D, C>,
+        assignment: FnvHashMap<V, D>,
+    ) -> Result<usize, SolveError<V>>
+    where
+        V: Clone + Copy + Eq + Hash,
+        D: Clone + Copy + PartialEq,
+        C: Clone + Constraint<V, D>,
+    {
+        let mut solutions = self.solutions(csp, assignment)?;
+        let count = solutions.tally();
+        match solutions.gave_up {
+            Some(reason) => Err(reason.into()),
+            None => Ok(count),
+        }
+    }
+
+    fn select_variable<V, D, C>(
+        &self,
+        csp: &CSP<V, D, C>,
+        assignment: &FnvHashMap<V, D>,
+        domains: &FnvHashMap<V, Vec<D>>,
+        weights: &[u32],
+    ) -> V
+    where
+        V: Clone + Copy + Eq + Hash,
+        D: Clone + Copy + PartialEq,
+        C: Clone + Constraint<V, D>,
+    {
+        let mut unassigned = csp.variables.iter().cloned().filter(|v| !assignment.contains_key(v));
+        // constraints on variable with other unassigned variables
+        let live_constraints = |variable: V| {
+            csp.variable_constraints[&variable].iter().cloned().filter(move |&c| {
+                csp.constraints[c].variables().iter().any(|v| *v != variable && !assignment.contains_key(v))
+            })
+        };
+        let degree = |variable: V| live_constraints(variable).count();
+        // min_by_key keeps the first of equal variables, so ties go to
+        // declaration order
+        match self.variable_order {
+            VariableOrder::Declaration => unassigned.next(),
+            VariableOrder::Degree => unassigned.min_by_key(|&v| std::cmp::Reverse(degree(v))),
+            VariableOrder::MinimumRemainingValues => {
+                unassigned.min_by_key(|&v| (domains[&v].len(), std::cmp::Reverse(degree(v))))
+            }
+            VariableOrder::DomainOverWeightedDegree => unassigned
+                .map(|v| {
+                    let weighted_degree: u32 = live_constraints(v).map(|c| weights[c]).sum();
+                    (v, domains[&v].len() as f64 / weighted_degree.max(1) as f64)
+                })
+                .fold(None, |best: Option<(V, f64)>, (v, ratio)| match best {
+                    Some((_, best_ratio)) if best_ratio <= ratio => best,
+                    _ => Some((v, ratio)),
+                })
+                .map(|(v, _)| v),
+        }
+        .expect("an unassigned variable")
+    }
+
+    fn order_values<V, D, C>(
+        &self,
+        csp: &CSP<V, D, C>,
+        variable: V,
+        assignment: &mut FnvHashMap<V, D>,
+        domains: &FnvHashMap<V, Vec<D>>,
+    ) -> Vec<D>
+    where
+        V: Clone + Copy + Eq + Hash,
+        D: Clone + Copy + PartialEq,
+        C: Clone + Constraint<V, D>,
+    {
+        let values = domains[&variable].clone();
+        match self.value_order {
+            ValueOrder::Domain => values,
+            ValueOrder::LeastConstraining => {
+                let mut ranked: Vec<(usize, D)> = values
+                    .into_iter()
+                    .map(|value| (csp.values_ruled_out(variable, value, assignment, domains), value))
+                    .collect();
+                // stable, so equally constraining values keep domain order
+                ranked.sort_by_key(|&(ruled_out, _)| ruled_out);
+                ranked.into_iter().map(|(_, value)| value).collect()
+            }
+        }
+    }
+}
+
+// Decides whether a partial assignment may be kept, see
+// Solutions::break_symmetry
+type SymmetryFilter<'a, V, D> = Box<dyn Fn(&FnvHashMap<V, D>) -> bool + 'a>;
+
+// Iterator over the solutions of a CSP, from Solver::solutions. The search
+// is depth first with a frame for every variable assigned so far, all
+// extending one assignment, so it needs no more call stack for large
+// problems than for small ones.
+pub struct Solutions<'a, V, D, C>
+where
+    V: Clone + Copy + Eq + Hash,
+    D: Clone + Copy + PartialEq,
+    C: Clone + Constraint<V, D>,
+{
+    solver: Solver,
+    csp: &'a CSP<V, D, C>,
+    assignment: FnvHashMap<V, D>,
+    domains: FnvHashMap<V, Vec<D>>,
+    trail: Trail<V, D>,
+    stack: Vec<Frame<V, D>>,
+    at_root: bool, // the initial assignment is consistent and not yet extended
+    weights: Vec<u32>, // for dom/wdeg, by constraint
+    symmetry: Option<SymmetryFilter<'a, V, D>>,
+    cancel: Option<CancelToken>,
+    deadline: Option<Instant>,
+    nodes: u64,
+    gave_up: Option<GaveUp>,
+}
+
+impl<'a, V, D, C> Solutions<'a, V, D, C>
+where
+    V: Clone + Copy + Eq + Hash,
+    D: Clone + Copy + PartialEq,
+    C: Clone + Constraint<V, D>,
+{
+    // Only search partial assignments that keep returns true for. Symmetric
+    // problems can be cut down to one solution out of each set of
+    // symmetric ones by keeping only assignments in some canonical form,
+    // e.g. with the first queen in the top half of the board.
+    pub fn break_symmetry<F: Fn(&FnvHashMap<V, D>) -> bool + 'a>(mut self, keep: F) -> Solutions<'a, V, D, C> {
+        self.symmetry = Some(Box::new(keep));
+        self
+    }
+
+    // Give up as soon as token is cancelled
+    pub fn cancel_with(mut self, token: CancelToken) -> Solutions<'a, V, D, C> {
+        self.cancel = Some(token);
+        self
+    }
+
+    // Why the search stopped early, if it did. Once it has, the iterator
+    // only returns None.
+    pub fn gave_up(&self) -> Option<GaveUp> {
+        self.gave_up
+    }
+
+    // How many values have been tried so far
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    // The next solution, Ok(None) once there are no more, or why the
+    // search gave up before finding out
+    pub fn try_next(&mut self) -> Result<Option<FnvHashMap<V, D>>, GaveUp> {
+        let solution = self.next();
+        match self.gave_up {
+            Some(reason) => Err(reason),
+            None => Ok(solution),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.assignment.len() == self.csp.variables.len()
+    }
+
+    // Count a node, and stop the search if it has run out of nodes or time
+    // or been cancelled. Only look at the clock every 1024 nodes, it is
+    // comparatively slow.
+    fn out_of_budget(&mut self) -> bool {
+        if self.gave_up.is_none() {
+            self.nodes += 1;
+            let over = |limit: Option<u64>| matches!(limit, Some(limit) if self.nodes > limit);
+            self.gave_up = if over(self.solver.node_limit) {
+                Some(GaveUp::NodeLimit)
+            } else if self.cancel.as_ref().map(CancelToken::is_cancelled) == Some(true) {
+                Some(GaveUp::Cancelled)
+            } else if self.nodes & 1023 == 0 && matches!(self.deadline, Some(deadline) if Instant::now() >= deadline) {
+                Some(GaveUp::TimeLimit)
+            } else {
+                None
+            };
+        }
+        if self.gave_up.is_some() {
+            self.stack.clear();
+            self.at_root = false;
+        }
+        self.gave_up.is_some()
+    }
+
+    // Take back the value of the deepest variable and whatever propagating
+    // it removed from the other domains
+    fn undo(&mut self) {
+        if let Some(frame) = self.stack.last() {
+            self.assignment.remove(&frame.variable);
+            while self.trail.len() > frame.trail {
+                let (variable, domain) = self.trail.pop().unwrap();
+                self.domains.insert(variable, domain);
+            }
+        }
+    }
+
+    // Start trying values for a variable the assignment leaves unassigned
+    fn push(&mut self) {
+        let solver = self.solver;
+        let variable = solver.select_variable(self.csp, &self.assignment, &self.domains, &self.weights);
+        let values = solver.order_values(self.csp, variable, &mut self.assignment, &self.domains);
+        self.stack.push(Frame {
+            variable,
+            values: values.into_iter(),
+            trail: self.trail.len(),
+        });
+    }
+
+    // Extend the assignment to the next consistent one, changing the value
+    // of the deepest variable and backing up as values run out. False once
+    // every value has been tried, or the search gave up.
+    fn next_state(&mut self) -> bool {
+        if self.at_root {
+            self.at_root = false;
+            return true;
+        }
+        loop {
+            self.undo();
+            let frame = match self.stack.last_mut() {
+                Some(frame) => frame,
+                None => return false,
+            };
+            let variable = frame.variable;
+            let value = match frame.values.next() {
+                Some(value) => value,
+                None => {
+                    self.stack.pop();
+                    continue;
                 }
+            };
+            if self.out_of_budget() {
+                return false;
+            }
+            self.assignment.insert(variable, value);
+            if let Some(keep) = &self.symmetry {
+                if !keep(&self.assignment) {
+                    continue;
+                }
+            }
+            let domain = std::mem::replace(self.domains.get_mut(&variable).unwrap(), vec![value]);
+            self.trail.push((variable, domain));
+            let csp = self.csp;
+            let consistent = match csp.conflict(variable, &self.assignment) {
+                Some(conflict) => Err(conflict),
+                None => csp.propagate(
+                    self.solver.propagation,
+                    variable,
+                    &mut self.assignment,
+                    &mut self.domains,
+                    &mut self.trail,
+                ),
+            };
+            match consistent {
+                Ok(()) => return true,
+                Err(conflict) => self.weights[conflict] += 1,
+            }
+        }
+    }
+
+    // Count the remaining solutions without building them: the values of
+    // the last variable are only checked, not assigned one by one
+    fn tally(&mut self) -> usize {
+        let mut count = 0;
+        let variables = self.csp.variables.len();
+        loop {
+            self.undo();
+            if self.assignment.len() + 1 == variables && !self.stack.is_empty() {
+                while let Some(value) = self.stack.last_mut().unwrap().values.next() {
+                    if self.out_of_budget() {
+                        return count;
+                    }
+                    let variable = self.stack.last().unwrap().variable;
+                    self.assignment.insert(variable, value);
+                    let keep = match &self.symmetry {
+                        Some(keep) => keep(&self.assignment),
+                        None => true,
+                    };
+                    count += (keep && self.csp.consistent(variable, &self.assignment)) as usize;
+                    self.assignment.remove(&variable);
+                }
+                self.stack.pop();
+                continue;
+            }
+            if !self.next_state() {
+                return count;
+            }
+            if self.is_complete() {
+                count += 1;
+            } else {
+                self.push();
+            }
+        }
+    }
+}
+
+impl<'a, V, D, C> Iterator for Solutions<'a, V, D, C>
+where
+    V: Clone + Copy + Eq + Hash,
+    D: Clone + Copy + PartialEq,
+    C: Clone + Constraint<V, D>,
+{
+    type Item = FnvHashMap<V, D>;
+
+    fn next(&mut self) -> Option<FnvHashMap<V, D>> {
+        loop {
+            if !self.next_state() {
+                return None;
+            }
+            if self.is_complete() {
+                return Some(self.assignment.clone());
             }
+            self.push();
         }
-        None
+    }
+
+    fn count(mut self) -> usize {
+        self.tally()
     }
 }
 
@@ -93,4 +858,273 @@ mod tests {
     fn ex1() {
         ()
     }
+
+    // Two variables must differ
+    #[derive(Clone, Debug)]
+    struct NotEqual(u8, u8);
+
+    impl Constraint<u8, u8> for NotEqual {
+        fn satisfied(&self, assignment: &FnvHashMap<u8, u8>) -> bool {
+            match (assignment.get(&self.0), assignment.get(&self.1)) {
+                (Some(a), Some(b)) => a != b,
+                _ => true,
+            }
+        }
+        fn variables(&self) -> Vec<u8> {
+            vec![self.0, self.1]
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let domains = |variables: &[u8]| variables.iter().map(|&v| (v, vec![0, 1])).collect::<FnvHashMap<u8, Vec<u8>>>();
+        let new = |variables: Vec<u8>, domains| CSP::<u8, u8, NotEqual>::new(variables, domains);
+        assert_eq!(new(vec![0, 1, 0], domains(&[0, 1])).err(), Some(CspError::DuplicateVariable(0)));
+        assert_eq!(new(vec![0, 1, 2], domains(&[0, 1])).err(), Some(CspError::MissingDomain(2)));
+        let mut empty = domains(&[0, 1]);
+        empty.insert(1, vec![]);
+        assert_eq!(new(vec![0, 1], empty).err(), Some(CspError::EmptyDomain(1)));
+        assert_eq!(CspError::EmptyDomain(1).to_string(), "variable 1 has an empty domain");
+        // domains of anything else do not matter
+        let mut csp = new(vec![0, 1], domains(&[0, 1, 2])).unwrap();
+        // a bad constraint is not added
+        assert_eq!(csp.add_constraint(NotEqual(0, 2)), Err(CspError::UnknownVariable(2)));
+        assert!(csp.constraints.is_empty() && csp.variable_constraints[&0].is_empty());
+        csp.add_constraint(NotEqual(0, 1)).unwrap();
+        // and an assignment to something else is an error
+        let mut assignment = FnvHashMap::default();
+        assignment.insert(2, 0);
+        assert_eq!(csp.check_assignment(&assignment), Err(CspError::UnknownVariable(2)));
+        for &propagation in &MODES {
+            let solver = Solver { propagation, ..Solver::default() };
+            let invalid = SolveError::Invalid(CspError::UnknownVariable(2));
+            assert_eq!(solver.try_solve(&csp, assignment.clone()), Err(invalid.clone()));
+            assert_eq!(solver.try_count_solutions(&csp, assignment.clone()), Err(invalid));
+            assert_eq!(solver.solutions(&csp, assignment.clone()).err(), Some(CspError::UnknownVariable(2)));
+            assert_eq!(solver.solve(&csp, assignment.clone()), None);
+            assert_eq!(solver.count_solutions(&csp, FnvHashMap::default()), 2);
+        }
+    }
+
+    // Queens in columns 0 to n - 1, valued by their rows, do not attack each
+    // other
+    #[derive(Clone, Debug)]
+    struct Queens(u8);
+
+    impl Constraint<u8, u8> for Queens {
+        fn satisfied(&self, assignment: &FnvHashMap<u8, u8>) -> bool {
+            assignment.iter().all(|(&c1, &r1)| {
+                assignment.iter().all(|(&c2, &r2)| {
+                    c1 == c2 || (r1 != r2 && (r1 as i32 - r2 as i32).abs() != (c1 as i32 - c2 as i32).abs())
+                })
+            })
+        }
+        fn variables(&self) -> Vec<u8> {
+            (0..self.0).collect()
+        }
+    }
+
+    fn queens(n: u8) -> CSP<u8, u8, Queens> {
+        let variables: Vec<u8> = (0..n).collect();
+        let domains = variables.iter().map(|&v| (v, variables.clone())).collect();
+        let mut csp = CSP::new(variables, domains).unwrap();
+        csp.add_constraint(Queens(n)).unwrap();
+        csp
+    }
+
+    const MODES: [Propagation; 3] = [
+        Propagation::Backtracking,
+        Propagation::ForwardChecking,
+        Propagation::ArcConsistency,
+    ];
+
+    #[test]
+    fn test_propagation() {
+        for &propagation in &MODES {
+            let solver = Solver { propagation, ..Solver::default() };
+            let csp = queens(8);
+            let solution = solver.solve(&csp, FnvHashMap::default()).unwrap();
+            assert_eq!(solution.len(), 8);
+            assert!(Queens(8).satisfied(&solution));
+            assert_eq!(solver.solve(&queens(3), FnvHashMap::default()), None);
+            // the initial assignment is kept
+            let mut initial = FnvHashMap::default();
+            initial.insert(1, 3);
+            assert_eq!(solver.solve(&csp, initial).unwrap()[&1], 3);
+        }
+    }
+
+    #[test]
+    fn test_arc_consistency() {
+        // a triangle cannot be coloured with two colours, which arc
+        // consistency finds once a corner is coloured
+        let variables = vec![0, 1, 2];
+        let domains = variables.iter().map(|&v| (v, vec![0, 1])).collect();
+        let mut csp = CSP::new(variables, domains).unwrap();
+        csp.add_constraint(NotEqual(0, 1)).unwrap();
+        csp.add_constraint(NotEqual(1, 2)).unwrap();
+        csp.add_constraint(NotEqual(0, 2)).unwrap();
+        let mut assignment = FnvHashMap::default();
+        assignment.insert(0, 0);
+        let mut domains = csp.domains.clone();
+        domains.insert(0, vec![0]);
+        assert_eq!(csp.forward_check(0, &mut assignment, &mut domains, &mut Vec::new()), Ok(()));
+        assert_eq!((domains[&1].clone(), domains[&2].clone()), (vec![1], vec![1]));
+        let arcs = csp.arcs_to(0, &assignment);
+        assert!(csp.ac3(arcs, &mut assignment, &mut csp.domains.clone(), &mut Vec::new()).is_err());
+        for &propagation in &MODES {
+            assert_eq!(Solver { propagation, ..Solver::default() }.solve(&csp, FnvHashMap::default()), None);
+        }
+        // and a chain can
+        let variables = vec![0, 1, 2];
+        let domains = variables.iter().map(|&v| (v, vec![0, 1])).collect();
+        let mut csp = CSP::new(variables, domains).unwrap();
+        csp.add_constraint(NotEqual(0, 1)).unwrap();
+        csp.add_constraint(NotEqual(1, 2)).unwrap();
+        for &propagation in &MODES {
+            let solution = Solver { propagation, ..Solver::default() }.solve(&csp, FnvHashMap::default()).unwrap();
+            assert_eq!((solution[&0], solution[&1], solution[&2]), (0, 1, 0));
+        }
+    }
+
+    const VARIABLE_ORDERS: [VariableOrder; 4] = [
+        VariableOrder::Declaration,
+        VariableOrder::Degree,
+        VariableOrder::MinimumRemainingValues,
+        VariableOrder::DomainOverWeightedDegree,
+    ];
+
+    #[test]
+    fn test_heuristics() {
+        // a star: 1 is next to everything, 3 has one colour to choose from
+        let variables = vec![0, 1, 2, 3];
+        let mut domains: FnvHashMap<u8, Vec<u8>> = variables.iter().map(|&v| (v, vec![0, 1, 2])).collect();
+        domains.insert(1, vec![2, 1, 0]);
+        domains.insert(3, vec![2]);
+        let mut csp = CSP::new(variables, domains).unwrap();
+        for &v in &[0, 2, 3] {
+            csp.add_constraint(NotEqual(1, v)).unwrap();
+        }
+        csp.add_constraint(NotEqual(0, 2)).unwrap();
+        let assignment = FnvHashMap::default();
+        let weights = vec![1; 4];
+        let pick = |variable_order| {
+            let solver = Solver { variable_order, ..Solver::default() };
+            solver.select_variable(&csp, &assignment, &csp.domains, &weights)
+        };
+        assert_eq!(pick(VariableOrder::Declaration), 0);
+        assert_eq!(pick(VariableOrder::Degree), 1);
+        assert_eq!(pick(VariableOrder::MinimumRemainingValues), 3);
+        // with equal weights 1 and 3 tie at one value per constraint
+        assert_eq!(pick(VariableOrder::DomainOverWeightedDegree), 1);
+        // until the constraint between them has caused some dead ends
+        let weights = vec![1, 1, 9, 1];
+        let solver = Solver { variable_order: VariableOrder::DomainOverWeightedDegree, ..Solver::default() };
+        assert_eq!(solver.select_variable(&csp, &assignment, &csp.domains, &weights), 3);
+        // 1 = 2 takes a colour from each of 0, 2 and 3, the others only
+        // from 0 and 2
+        let solver = Solver { value_order: ValueOrder::LeastConstraining, ..Solver::default() };
+        let mut assignment = FnvHashMap::default();
+        assert_eq!(solver.order_values(&csp, 1, &mut assignment, &csp.domains), vec![1, 0, 2]);
+
+        // every combination solves the same problems
+        for &propagation in &MODES {
+            for &variable_order in &VARIABLE_ORDERS {
+                for &value_order in &[ValueOrder::Domain, ValueOrder::LeastConstraining] {
+                    let solver = Solver { propagation, variable_order, value_order, ..Solver::default() };
+                    let solution = solver.solve(&queens(8), FnvHashMap::default()).unwrap();
+                    assert!(Queens(8).satisfied(&solution) && solution.len() == 8);
+                    assert_eq!(solver.solve(&queens(3), FnvHashMap::default()), None);
+                    let solution = solver.solve(&csp, FnvHashMap::default()).unwrap();
+                    assert_eq!(solution[&3], 2);
+                    assert!(csp.constraints.iter().all(|c| c.satisfied(&solution)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_solutions() {
+        for &propagation in &MODES {
+            for &variable_order in &VARIABLE_ORDERS {
+                let solver = Solver { propagation, variable_order, ..Solver::default() };
+                let csp = queens(8);
+                assert_eq!(solver.count_solutions(&csp, FnvHashMap::default()), 92);
+                assert_eq!(solver.count_solutions(&queens(3), FnvHashMap::default()), 0);
+                // the iterator finds the same solutions, all different
+                let solutions: Vec<_> = solver.solutions(&queens(6), FnvHashMap::default()).unwrap().collect();
+                assert_eq!(solutions.len(), 4);
+                assert!(solutions.iter().all(|s| s.len() == 6 && Queens(6).satisfied(s)));
+                for (i, a) in solutions.iter().enumerate() {
+                    assert!(solutions[i + 1..].iter().all(|b| a != b));
+                }
+                // one of each mirror image pair
+                let top_half = |assignment: &FnvHashMap<u8, u8>| !matches!(assignment.get(&0), Some(&row) if row >= 4);
+                assert_eq!(solver.solutions(&csp, FnvHashMap::default()).unwrap().break_symmetry(top_half).count(), 46);
+                let mut first = FnvHashMap::default();
+                first.insert(0, 0);
+                assert_eq!(solver.count_solutions(&csp, first), 4);
+            }
+        }
+        // stopping early, e.g. to see whether a puzzle has a unique answer
+        let variables = vec![0, 1, 2];
+        let domains = variables.iter().map(|&v| (v, vec![0, 1])).collect();
+        let mut csp = CSP::new(variables, domains).unwrap();
+        csp.add_constraint(NotEqual(0, 1)).unwrap();
+        csp.add_constraint(NotEqual(1, 2)).unwrap();
+        let solver = Solver::default();
+        assert_eq!(solver.solutions(&csp, FnvHashMap::default()).unwrap().take(2).count(), 2);
+        let mut assignment = FnvHashMap::default();
+        assignment.insert(1, 1);
+        assert_eq!(solver.solutions(&csp, assignment).unwrap().take(2).count(), 1);
+        // a complete assignment is its own only solution
+        let solution = solver.solve(&csp, FnvHashMap::default()).unwrap();
+        assert_eq!(solver.count_solutions(&csp, solution.clone()), 1);
+        assert_eq!(solver.solutions(&csp, solution.clone()).unwrap().next(), Some(solution));
+        // but not one that breaks a constraint, or takes a value outside
+        // the domain
+        for &propagation in &MODES {
+            let solver = Solver { propagation, ..Solver::default() };
+            let mut conflicting = FnvHashMap::default();
+            conflicting.insert(0, 0);
+            conflicting.insert(1, 0);
+            assert_eq!(solver.solve(&csp, conflicting.clone()), None);
+            assert_eq!(solver.count_solutions(&csp, conflicting), 0);
+            let mut outside = FnvHashMap::default();
+            outside.insert(0, 9);
+            assert_eq!(solver.solve(&csp, outside.clone()), None);
+            assert_eq!(solver.count_solutions(&csp, outside.clone()), 0);
+            outside.insert(1, 1);
+            outside.insert(2, 0);
+            assert_eq!(solver.solve(&csp, outside), None);
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let (eight, six) = (queens(8), queens(6));
+        for &propagation in &MODES {
+            let solver = Solver { propagation, node_limit: Some(5), ..Solver::default() };
+            assert_eq!(solver.try_solve(&queens(8), FnvHashMap::default()), Err(GaveUp::NodeLimit.into()));
+            assert_eq!(solver.try_count_solutions(&queens(8), FnvHashMap::default()), Err(GaveUp::NodeLimit.into()));
+            let mut solutions = solver.solutions(&eight, FnvHashMap::default()).unwrap();
+            assert_eq!(solutions.next(), None);
+            assert_eq!((solutions.gave_up(), solutions.nodes()), (Some(GaveUp::NodeLimit), 6));
+            assert_eq!(solutions.next(), None);
+            // given enough nodes the answer is the same as without a limit
+            let solver = Solver { node_limit: Some(100_000), ..solver };
+            assert!(solver.try_solve(&queens(8), FnvHashMap::default()).unwrap().is_some());
+            assert_eq!(solver.try_solve(&queens(3), FnvHashMap::default()), Ok(None));
+            assert_eq!(solver.try_count_solutions(&queens(6), FnvHashMap::default()), Ok(4));
+            // a solution found before giving up is kept
+            let mut solutions = solver.solutions(&six, FnvHashMap::default()).unwrap();
+            assert!(solutions.try_next().unwrap().is_some());
+            let token = CancelToken::new();
+            let mut solutions = solutions.cancel_with(token.clone());
+            token.cancel();
+            assert_eq!(solutions.try_next(), Err(GaveUp::Cancelled));
+            let solver = Solver { time_limit: Some(Duration::from_secs(0)), ..Solver::default() };
+            assert_eq!(solver.try_count_solutions(&queens(10), FnvHashMap::default()), Err(GaveUp::TimeLimit.into()));
+        }
+    }
 }
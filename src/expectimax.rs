@@ -0,0 +1,168 @@
+//! Expectiminimax
+//! Search for two-player games of chance. Players pick their best (or the
+//! opponent's worst) move as in minimax, while chance nodes score the
+//! average over the dice, weighted by probability.
+
+use crate::board::{ChanceBoard, Move, Outcome, Piece};
+use crate::minimax::{SearchResult, WIN_SCORE};
+
+// Expectiminimax search engine
+pub struct Expectiminimax {
+    // Number of moves to look ahead after the root move. Chance nodes do
+    // not count towards it.
+    pub max_depth: i32,
+    // Score of a won game. Board::evaluate must stay strictly between
+    // -win_score and win_score. Star1 relies on this bound to prune chance
+    // nodes, so the tighter it is the more it saves.
+    pub win_score: f32,
+    // Prune chance nodes with Ballard's Star1 algorithm. The result is the
+    // same either way.
+    pub star1: bool,
+}
+
+impl Default for Expectiminimax {
+    fn default() -> Expectiminimax {
+        Expectiminimax {
+            max_depth: 4,
+            win_score: WIN_SCORE,
+            star1: true,
+        }
+    }
+}
+
+impl Expectiminimax {
+    // Find the move with the best expected score for the side to move.
+    // There is no best move at a chance node, so the result has none.
+    // The principal variation is just the best move, as what follows it
+    // depends on the dice.
+    pub fn search<B, P>(&self, board: &B) -> SearchResult
+    where
+        B: ChanceBoard<P>,
+        P: Piece,
+    {
+        let player = board.turn();
+        let mut result = SearchResult {
+            best_move: None,
+            score: f32::NEG_INFINITY,
+            pv: Vec::new(),
+            nodes: 0,
+            depth: self.max_depth,
+        };
+        if board.is_chance_node() || board.outcome().is_some() {
+            result.score = self.value(board, 0, player, -self.win_score, self.win_score, &mut result.nodes);
+            return result;
+        }
+        for m in board.legal_moves() {
+            let alpha = result.score.max(-self.win_score);
+            let eval = self.value(&board.make_move(m), self.max_depth, player, alpha, self.win_score, &mut result.nodes);
+            if eval > result.score {
+                result.score = eval;
+                result.best_move = Some(m);
+                result.pv = vec![m];
+            }
+        }
+        result
+    }
+
+    // Value of the position for player, within the window (alpha, beta).
+    // Like alpha-beta it only promises a bound when the value falls
+    // outside the window.
+    fn value<B, P>(&self, board: &B, max_depth: i32, player: P, alpha: f32, beta: f32, nodes: &mut u64) -> f32
+    where
+        B: ChanceBoard<P>,
+        P: Piece,
+    {
+        *nodes += 1;
+        if let Some(outcome) = board.outcome() {
+            let score = match outcome {
+                Outcome::Win => self.win_score,
+                Outcome::Loss => -self.win_score,
+                Outcome::Draw => 0.0,
+            };
+            return if board.turn() == player { score } else { -score };
+        }
+        if board.is_chance_node() {
+            let outcomes = board.chance_outcomes();
+            if self.star1 {
+                return self.star1(board, &outcomes, max_depth, player, alpha, beta, nodes);
+            }
+            return outcomes
+                .iter()
+                .map(|&(m, p)| {
+                    let child = board.make_move(m);
+                    p * self.value(&child, max_depth, player, -self.win_score, self.win_score, nodes)
+                })
+                .sum();
+        }
+        if max_depth == 0 {
+            return board.evaluate(player);
+        }
+        if board.turn() == player {
+            let mut a = alpha;
+            for m in board.legal_moves() {
+                a = a.max(self.value(&board.make_move(m), max_depth - 1, player, a, beta, nodes));
+                if a >= beta {
+                    break;
+                }
+            }
+            a
+        } else {
+            let mut b = beta;
+            for m in board.legal_moves() {
+                b = b.min(self.value(&board.make_move(m), max_depth - 1, player, alpha, b, nodes));
+                if b <= alpha {
+                    break;
+                }
+            }
+            b
+        }
+    }
+
+    // Star1: every score lies within +-win_score, so after some outcomes
+    // have been searched the average is already known to within a range.
+    // Each outcome is searched with the window that could still move the
+    // average into (alpha, beta), and the search stops once it cannot.
+    #[allow(clippy::too_many_arguments)]
+    fn star1<B, P>(
+        &self,
+        board: &B,
+        outcomes: &[(Move, f32)],
+        max_depth: i32,
+        player: P,
+        alpha: f32,
+        beta: f32,
+        nodes: &mut u64,
+    ) -> f32
+    where
+        B: ChanceBoard<P>,
+        P: Piece,
+    {
+        let (lower, upper) = (-self.win_score, self.win_score);
+        let mut sum = 0.0; // probability weighted scores so far
+        let mut remaining = 1.0; // probability not yet searched
+        for &(m, p) in outcomes {
+            remaining -= p;
+            // the average fails low if this outcome scores at most
+            // child_alpha, and high if it scores at least child_beta. A
+            // window clipped to the score bounds cannot fail that side.
+            let child_alpha = (alpha - sum - remaining * upper) / p;
+            let child_beta = (beta - sum - remaining * lower) / p;
+            let v = self.value(
+                &board.make_move(m),
+                max_depth,
+                player,
+                child_alpha.max(lower),
+                child_beta.min(upper),
+                nodes,
+            );
+            sum += p * v;
+            if v <= child_alpha {
+                return sum + remaining * upper; // at most alpha
+            }
+            if v >= child_beta {
+                return sum + remaining * lower; // at least beta
+            }
+        }
+        sum
+    }
+}
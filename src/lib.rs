@@ -7,3 +7,10 @@
 pub mod csp;
 pub mod board;
 pub mod minimax;
+pub mod mcts;
+pub mod expectimax;
+pub mod multiplayer;
+pub mod protocol;
+pub mod record;
+pub mod tournament;
+pub mod transposition;
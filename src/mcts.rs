@@ -0,0 +1,207 @@
+//! Monte Carlo tree search
+//! UCT search that judges moves by random playouts to the end of the game
+//! instead of by Board::evaluate
+
+use crate::board::{Board, Move, Outcome, Piece};
+use crate::minimax::SearchResult;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use std::time::{Duration, Instant};
+
+// UCT search engine. Two engines built with the same seed and settings
+// make the same choices.
+pub struct Mcts {
+    // Stop after this many playouts
+    pub iterations: Option<u64>,
+    // Stop once this much time has passed
+    pub time_limit: Option<Duration>,
+    // Weight of the exploration term in the UCT formula
+    pub exploration: f32,
+    // Playouts still running after this many moves are decided by
+    // Board::evaluate, for games that can go on forever
+    pub max_playout_moves: u32,
+    rng: StdRng,
+}
+
+struct Node<B> {
+    board: B,
+    parent: Option<usize>,
+    last_move: Option<Move>,
+    children: Vec<usize>,
+    untried: Vec<Move>,
+    visits: u64,
+    reward: f32, // total for the player who made last_move
+}
+
+impl<B> Node<B> {
+    fn new<P: Piece>(board: B, parent: Option<usize>, last_move: Option<Move>) -> Node<B>
+    where
+        B: Board<P>,
+    {
+        let mut untried = match board.outcome() {
+            Some(_) => Vec::new(),
+            None => board.legal_moves(),
+        };
+        // boards list their moves in any order, sort them so the seed alone
+        // decides which are tried first
+        untried.sort();
+        untried.reverse(); // moves are popped off the end
+        Node {
+            board,
+            parent,
+            last_move,
+            children: Vec::new(),
+            untried,
+            visits: 0,
+            reward: 0.0,
+        }
+    }
+
+    fn mean_reward(&self) -> f32 {
+        self.reward / self.visits as f32
+    }
+}
+
+// Reward for player from a finished playout: 1 for a win, 0.5 for a draw
+fn reward<P: Piece>(player: P, turn: P, outcome: Outcome) -> f32 {
+    match outcome {
+        Outcome::Draw => 0.5,
+        Outcome::Win if player == turn => 1.0,
+        Outcome::Loss if player != turn => 1.0,
+        _ => 0.0,
+    }
+}
+
+impl Mcts {
+    // Engine running 10000 playouts per move with the usual sqrt(2)
+    // exploration weight
+    pub fn new(seed: u64) -> Mcts {
+        Mcts {
+            iterations: Some(10_000),
+            time_limit: None,
+            exploration: std::f32::consts::SQRT_2,
+            max_playout_moves: 1000,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // Run playouts until the iteration or time budget is used up. The best
+    // move is the most visited one and the score is its mean result for
+    // the side to move, from -1 (always lost) to 1 (always won). nodes
+    // counts playouts and depth is the deepest point the tree reached.
+    pub fn search<B, P>(&mut self, board: &B) -> SearchResult
+    where
+        B: Board<P> + Clone,
+        P: Piece,
+    {
+        let deadline = self.time_limit.map(|limit| Instant::now() + limit);
+        let mut tree = vec![Node::new(board.clone(), None, None)];
+        let mut iterations = 0;
+        let mut max_depth = 0;
+        loop {
+            if self.iterations.filter(|&n| iterations >= n).is_some()
+                || deadline.filter(|&d| Instant::now() >= d).is_some()
+            {
+                break;
+            }
+            // Selection - follow the most promising fully expanded nodes
+            let mut node = 0;
+            let mut depth = 0;
+            while tree[node].untried.is_empty() && !tree[node].children.is_empty() {
+                node = self.select_child(&tree, node);
+                depth += 1;
+            }
+            // Expansion - add one new position to the tree
+            if let Some(m) = tree[node].untried.pop() {
+                let child = Node::new(tree[node].board.make_move(m), Some(node), Some(m));
+                tree.push(child);
+                let child = tree.len() - 1;
+                tree[node].children.push(child);
+                node = child;
+                depth += 1;
+            }
+            max_depth = max_depth.max(depth);
+            // Simulation - play randomly to the end of the game
+            let (turn, outcome) = self.playout(&tree[node].board);
+            // Backpropagation - credit each move on the way back to the root
+            let mut current = Some(node);
+            while let Some(n) = current {
+                tree[n].visits += 1;
+                if let Some(parent) = tree[n].parent {
+                    tree[n].reward += reward(tree[parent].board.turn(), turn, outcome);
+                }
+                current = tree[n].parent;
+            }
+            iterations += 1;
+        }
+        let mut result = SearchResult {
+            best_move: None,
+            score: 0.0,
+            pv: Vec::new(),
+            nodes: iterations,
+            depth: max_depth,
+        };
+        let mut node = 0;
+        while let Some(&child) = tree[node].children.iter().max_by_key(|&&c| tree[c].visits) {
+            if node == 0 {
+                result.best_move = tree[child].last_move;
+                result.score = 2.0 * tree[child].mean_reward() - 1.0;
+            }
+            result.pv.push(tree[child].last_move.unwrap());
+            node = child;
+        }
+        if result.best_move.is_none() {
+            // not even one playout, any legal move will have to do
+            result.best_move = tree[0].untried.last().cloned();
+        }
+        result
+    }
+
+    fn select_child<B>(&self, tree: &[Node<B>], node: usize) -> usize {
+        let log_visits = (tree[node].visits as f32).ln();
+        let uct = |c: usize| {
+            let child = &tree[c];
+            child.mean_reward() + self.exploration * (log_visits / child.visits as f32).sqrt()
+        };
+        let mut best = tree[node].children[0];
+        for &c in tree[node].children.iter().skip(1) {
+            if uct(c) > uct(best) {
+                best = c;
+            }
+        }
+        best
+    }
+
+    // Play random moves to the end of the game and return the side to move
+    // in the final position with its result
+    fn playout<B, P>(&mut self, board: &B) -> (P, Outcome)
+    where
+        B: Board<P> + Clone,
+        P: Piece,
+    {
+        let mut board = board.clone();
+        for _ in 0..self.max_playout_moves {
+            if let Some(outcome) = board.outcome() {
+                return (board.turn(), outcome);
+            }
+            let mut moves = board.legal_moves();
+            if moves.is_empty() {
+                return (board.turn(), Outcome::Draw);
+            }
+            moves.sort();
+            board = board.make_move(moves[self.rng.gen_range(0, moves.len())]);
+        }
+        if let Some(outcome) = board.outcome() {
+            return (board.turn(), outcome);
+        }
+        let eval = board.evaluate(board.turn());
+        let outcome = if eval > 0.0 {
+            Outcome::Win
+        } else if eval < 0.0 {
+            Outcome::Loss
+        } else {
+            Outcome::Draw
+        };
+        (board.turn(), outcome)
+    }
+}
@@ -1,100 +1,540 @@
-use crate::board::{Board, Move, Piece};
+use crate::board::{Board, Move, Outcome, Piece};
+use crate::transposition::{Bound, Entry, TranspositionTable};
+use fnv::FnvHashMap;
+use rayon::prelude::*;
+use std::cmp;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
 
-fn f32_max(a: f32, b: f32) -> f32 {
-    if a < b {
-        b
+// Score of a won game for the winner, less one for every ply it takes to
+// get there so that faster wins (and slower losses) score better.
+// Board::evaluate should stay far below this, under WIN_SCORE / 2.
+pub const WIN_SCORE: f32 = 1.0e7;
+
+// Score of a finished game for the side to move, ply moves from the root
+fn outcome_score(outcome: Outcome, ply: i32) -> f32 {
+    match outcome {
+        Outcome::Win => WIN_SCORE - ply as f32,
+        Outcome::Loss => -WIN_SCORE + ply as f32,
+        Outcome::Draw => 0.0,
+    }
+}
+
+// Find the best possible outcome for the side to move
+fn minimax<B: Board<P>, P: Piece>(board: B, max_depth: i32, ply: i32) -> f32 {
+    // Base case – terminal position or maximum depth reached
+    if let Some(outcome) = board.outcome() {
+        return outcome_score(outcome, ply);
+    }
+    if max_depth == 0 {
+        return board.evaluate(board.turn());
+    }
+    // Recursive case - the opponent's gains are our losses, unless the
+    // same player moves again
+    let mut best_eval = f32::NEG_INFINITY; // arbitrarily low starting point
+    for m in board.legal_moves() {
+        let child = board.make_move(m);
+        let result = if child.turn() == board.turn() {
+            minimax(child, max_depth - 1, ply + 1)
+        } else {
+            -minimax(child, max_depth - 1, ply + 1)
+        };
+        best_eval = best_eval.max(result); // we want the move with the highest evaluation
+    }
+    best_eval
+}
+
+// What a search found: the move to play, its evaluation for the side to
+// move, the line of play expected to follow (starting with best_move), the
+// number of positions visited and the depth searched. best_move is None
+// only when there are no legal moves.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub score: f32,
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+    pub depth: i32,
+}
+
+// Which tricks Searcher uses to search the most promising moves first.
+// Whatever is chosen, the table move or the best line from the previous
+// iteration always goes first.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MoveOrdering {
+    pub board_scores: bool, // sort by Board::move_score
+    pub killers: bool,      // moves that caused a cutoff at the same ply
+    pub history: bool,      // moves that caused cutoffs anywhere, weighted by depth
+}
+
+impl MoveOrdering {
+    pub const NONE: MoveOrdering = MoveOrdering {
+        board_scores: false,
+        killers: false,
+        history: false,
+    };
+    pub const ALL: MoveOrdering = MoveOrdering {
+        board_scores: true,
+        killers: true,
+        history: true,
+    };
+}
+
+impl Default for MoveOrdering {
+    fn default() -> MoveOrdering {
+        MoveOrdering::ALL
+    }
+}
+
+// Table depth for results that reached the end of the game on every line,
+// which hold no matter how deep the search is
+const SOLVED_DEPTH: i32 = i32::MAX;
+
+// Win and loss scores count plies from the root, but the table is shared
+// between positions at different plies, so it stores them counted from
+// the position itself instead
+fn score_to_table(score: f32, ply: i32) -> f32 {
+    if score > WIN_SCORE / 2.0 {
+        score + ply as f32
+    } else if score < -WIN_SCORE / 2.0 {
+        score - ply as f32
     } else {
-        a
+        score
+    }
+}
+
+fn score_from_table(score: f32, ply: i32) -> f32 {
+    score_to_table(score, -ply)
+}
+
+// Receives events from Searcher as it works. Every method does nothing by
+// default, so an observer only implements what it needs, and searches
+// without an observer use () which compiles down to nothing at all.
+pub trait SearchObserver {
+    // A position was entered, ply moves from the root
+    fn node(&mut self, _ply: i32) {}
+    // A position at the depth limit was scored with Board::evaluate
+    fn leaf(&mut self, _ply: i32) {}
+    // The move_index-th move searched at ply caused a beta cutoff
+    fn cutoff(&mut self, _ply: i32, _move_index: usize) {}
+    // Iterative deepening finished the search to result.depth
+    fn iteration(&mut self, _result: &SearchResult, _elapsed: Duration) {}
+}
+
+impl SearchObserver for () {}
+
+// Observer that counts what the search did, for comparing engine changes
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchStats {
+    pub nodes: u64,
+    pub leaf_evals: u64,
+    pub nodes_per_ply: Vec<u64>,
+    pub cutoffs_per_ply: Vec<u64>,
+    pub first_move_cutoffs: u64, // cutoffs by the first move tried, a sign of good ordering
+    pub nodes_per_iteration: Vec<u64>,
+    pub depth: i32,
+    pub elapsed: Duration,
+}
+
+impl SearchStats {
+    pub fn cutoffs(&self) -> u64 {
+        self.cutoffs_per_ply.iter().sum()
+    }
+
+    // Effective branching factor: how many times more nodes the last
+    // iteration needed than the one before it
+    pub fn branching_factor(&self) -> Option<f64> {
+        match self.nodes_per_iteration.as_slice() {
+            [.., before, last] if *before > 0 => Some(*last as f64 / *before as f64),
+            _ => None,
+        }
+    }
+}
+
+fn count_at(counts: &mut Vec<u64>, ply: i32) {
+    let ply = ply as usize;
+    if counts.len() <= ply {
+        counts.resize(ply + 1, 0);
+    }
+    counts[ply] += 1;
+}
+
+impl SearchObserver for SearchStats {
+    fn node(&mut self, ply: i32) {
+        self.nodes += 1;
+        count_at(&mut self.nodes_per_ply, ply);
+    }
+    fn leaf(&mut self, _ply: i32) {
+        self.leaf_evals += 1;
+    }
+    fn cutoff(&mut self, ply: i32, move_index: usize) {
+        count_at(&mut self.cutoffs_per_ply, ply);
+        if move_index == 0 {
+            self.first_move_cutoffs += 1;
+        }
     }
+    fn iteration(&mut self, result: &SearchResult, elapsed: Duration) {
+        let so_far: u64 = self.nodes_per_iteration.iter().sum();
+        self.nodes_per_iteration.push(result.nodes - so_far);
+        self.depth = result.depth;
+        self.elapsed = elapsed;
+    }
+}
+
+// State shared by every node of one alpha-beta search
+struct SearchContext<'a, O> {
+    deadline: Option<Instant>,
+    table: Option<&'a mut TranspositionTable>,
+    observer: &'a mut O,
+    nodes: u64,
+    aborted: bool,
+    depth_limited: bool, // true if some line was cut off by max_depth
+    ordering: MoveOrdering,
+    quiescence_depth: i32,
+    killers: Vec<[Option<Move>; 2]>, // by ply
+    history: FnvHashMap<Move, u64>,
+    pv: Vec<Move>, // best line of the previous iteration
+    on_pv: bool,   // whether the current node lies on pv
 }
-fn f32_min(a: f32, b: f32) -> f32 {
-    if a < b {
-        a
+
+impl<'a, O: SearchObserver> SearchContext<'a, O> {
+    fn new(
+        deadline: Option<Instant>,
+        table: Option<&'a mut TranspositionTable>,
+        ordering: MoveOrdering,
+        observer: &'a mut O,
+    ) -> SearchContext<'a, O> {
+        SearchContext {
+            deadline,
+            table,
+            observer,
+            nodes: 0,
+            aborted: false,
+            depth_limited: false,
+            ordering,
+            quiescence_depth: 0,
+            killers: Vec::new(),
+            history: FnvHashMap::default(),
+            pv: Vec::new(),
+            on_pv: false,
+        }
+    }
+
+    // Only look at the clock every 1024 nodes, it is comparatively slow
+    fn out_of_time(&mut self) -> bool {
+        if !self.aborted && self.nodes & 1023 == 0 {
+            if let Some(deadline) = self.deadline {
+                self.aborted = Instant::now() >= deadline;
+            }
+        }
+        self.aborted
+    }
+
+    fn probe(&self, key: Option<u64>, ply: i32) -> Option<Entry> {
+        let entry = *self.table.as_ref()?.get(key?)?;
+        Some(Entry {
+            score: score_from_table(entry.score, ply),
+            ..entry
+        })
+    }
+
+    fn store(&mut self, key: Option<u64>, ply: i32, entry: Entry) {
+        if self.aborted {
+            return; // scores from an interrupted search are meaningless
+        }
+        if let (Some(key), Some(table)) = (key, self.table.as_mut()) {
+            table.insert(Entry {
+                key,
+                score: score_to_table(entry.score, ply),
+                ..entry
+            });
+        }
+    }
+
+    // Put first (the table's or the previous iteration's choice) at the
+    // front, then killers, then the rest by board score and history. The
+    // sort is stable, so with nothing to go on the board's order is kept.
+    fn order_moves<B: Board<P>, P: Piece>(&self, board: &B, moves: &mut Vec<Move>, ply: i32, first: Option<Move>) {
+        let ordering = self.ordering;
+        if ordering == MoveOrdering::NONE {
+            if let Some(i) = moves.iter().position(|&m| Some(m) == first) {
+                moves[..=i].rotate_right(1);
+            }
+            return;
+        }
+        let killers = match self.killers.get(ply as usize) {
+            Some(&killers) if ordering.killers => killers,
+            _ => [None, None],
+        };
+        let mut ranked: Vec<(u8, f32, u64, Move)> = moves
+            .iter()
+            .map(|&m| {
+                let class = if Some(m) == first {
+                    2
+                } else if killers.contains(&Some(m)) {
+                    1
+                } else {
+                    0
+                };
+                let hint = if ordering.board_scores { board.move_score(m) } else { 0.0 };
+                let history = if ordering.history { self.history.get(&m).cloned().unwrap_or(0) } else { 0 };
+                (class, hint, history, m)
+            })
+            .collect();
+        ranked.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then(b.1.partial_cmp(&a.1).unwrap_or(cmp::Ordering::Equal))
+                .then(b.2.cmp(&a.2))
+        });
+        moves.clear();
+        moves.extend(ranked.into_iter().map(|(_, _, _, m)| m));
+    }
+
+    // Remember a move that was good enough to cut off the search
+    fn record_cutoff(&mut self, m: Move, ply: i32, depth: i32) {
+        if self.ordering.killers {
+            let ply = ply as usize;
+            if self.killers.len() <= ply {
+                self.killers.resize(ply + 1, [None, None]);
+            }
+            if self.killers[ply][0] != Some(m) {
+                self.killers[ply][1] = self.killers[ply][0];
+                self.killers[ply][0] = Some(m);
+            }
+        }
+        if self.ordering.history {
+            *self.history.entry(m).or_insert(0) += (depth * depth) as u64;
+        }
+    }
+}
+
+// Score of child, the position after one of board's moves, for the side
+// to move in board: the opponent's score negated, or as it is when the same
+// player moves again (extra turns, as in Kalah). alpha and beta are
+// board's window.
+#[allow(clippy::too_many_arguments)]
+fn child_score<B: Board<P>, P: Piece, O: SearchObserver>(
+    board: &B,
+    child: &B,
+    max_depth: i32,
+    ply: i32,
+    alpha: f32,
+    beta: f32,
+    ctx: &mut SearchContext<O>,
+    pv: &mut Vec<Move>,
+) -> f32 {
+    if child.turn() == board.turn() {
+        negamax(child, max_depth, ply, alpha, beta, ctx, pv)
     } else {
-        b
+        -negamax(child, max_depth, ply, -beta, -alpha, ctx, pv)
     }
 }
 
-// Find the best possible outcome for original player
-fn minimax<B: Board<P>, P: Piece>(
-    board: B,
-    maximizing: bool,
-    original_player: P,
+// Alpha-beta search in negamax form: every score is for the side to move,
+// so the opponent's best score is negated to get ours
+fn negamax<B: Board<P>, P: Piece, O: SearchObserver>(
+    board: &B,
     max_depth: i32,
+    ply: i32,
+    alpha: f32,
+    beta: f32,
+    ctx: &mut SearchContext<O>,
+    pv: &mut Vec<Move>,
 ) -> f32 {
+    // pv receives the expected line of play from here if the score is exact
+    ctx.nodes += 1;
+    ctx.observer.node(ply);
+    if ctx.out_of_time() {
+        return 0.0; // result is thrown away by the caller
+    }
     // Base case – terminal position or maximum depth reached
-    if board.is_win() || board.is_draw() || max_depth == 0 {
-        return board.evaluate(original_player);
+    if let Some(outcome) = board.outcome() {
+        return outcome_score(outcome, ply);
     }
-    // Recursive case - maximize your gains or minimize the opponent's gains
-    if maximizing {
-        let mut best_eval = f32::NEG_INFINITY; // arbitrarily low starting point
-        for m in board.legal_moves() {
-            let result = minimax(board.make_move(m), false, original_player, max_depth - 1);
-            best_eval = f32_max(result, best_eval); // we want the move with the highest evaluation
+    if max_depth == 0 {
+        if ctx.quiescence_depth > 0 {
+            let quiescence_depth = ctx.quiescence_depth;
+            return quiesce(board, quiescence_depth, ply, alpha, beta, ctx);
         }
-        return best_eval;
-    } else {
-        // minimizing
-        let mut worst_eval = f32::INFINITY;
-        for m in board.legal_moves() {
-            let result = minimax(board.make_move(m), true, original_player, max_depth - 1);
-            worst_eval = f32_min(result, worst_eval); // we want the move with the lowest evaluation
+        ctx.depth_limited = true;
+        ctx.observer.leaf(ply);
+        return board.evaluate(board.turn());
+    }
+    // Reuse an earlier result for this position if it is deep enough
+    let key = board.hash_key();
+    let mut table_move = None;
+    if let Some(entry) = ctx.probe(key, ply) {
+        table_move = entry.best_move;
+        if entry.depth >= max_depth {
+            let usable = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => entry.score >= beta,
+                Bound::Upper => entry.score <= alpha,
+            };
+            if usable {
+                ctx.depth_limited |= entry.depth != SOLVED_DEPTH;
+                return entry.score;
+            }
+        }
+    }
+    let outer_depth_limited = ctx.depth_limited;
+    ctx.depth_limited = false;
+    let on_pv = ctx.on_pv;
+    let pv_move = if on_pv { ctx.pv.get(ply as usize).cloned() } else { None };
+    let mut moves = board.legal_moves();
+    ctx.order_moves(board, &mut moves, ply, table_move.or(pv_move));
+    let mut a = alpha;
+    let mut best_move = None;
+    let mut line = Vec::new();
+    // Recursive case - the opponent's gains are our losses
+    for (i, m) in moves.into_iter().enumerate() {
+        line.clear();
+        ctx.on_pv = on_pv && Some(m) == pv_move;
+        let result = child_score(board, &board.make_move(m), max_depth - 1, ply + 1, a, beta, ctx, &mut line);
+        if result > a {
+            a = result;
+            best_move = Some(m);
+            pv.clear();
+            pv.push(m);
+            pv.extend_from_slice(&line);
+        }
+        if a >= beta {
+            ctx.record_cutoff(m, ply, max_depth);
+            ctx.observer.cutoff(ply, i);
+            break;
         }
-        return worst_eval;
     }
+    ctx.on_pv = false;
+    let bound = if a <= alpha {
+        Bound::Upper
+    } else if a >= beta {
+        Bound::Lower
+    } else {
+        Bound::Exact
+    };
+    let depth = if ctx.depth_limited { max_depth } else { SOLVED_DEPTH };
+    ctx.depth_limited |= outer_depth_limited;
+    ctx.store(
+        key,
+        ply,
+        Entry {
+            key: 0,
+            depth,
+            score: a,
+            bound,
+            best_move,
+        },
+    );
+    a
 }
 
-fn alphabeta<B: Board<P>, P: Piece>(
-    board: B,
-    maximizing: bool,
-    original_player: P,
+// Quiescence search: past the depth limit, keep following noisy moves for
+// up to max_depth more plies so the position is only evaluated once it has
+// calmed down. The side to move may also stop ("stand pat") and take the
+// evaluation if no noisy move improves on it.
+fn quiesce<B: Board<P>, P: Piece, O: SearchObserver>(
+    board: &B,
     max_depth: i32,
+    ply: i32,
     alpha: f32,
     beta: f32,
+    ctx: &mut SearchContext<O>,
 ) -> f32 {
-    // defaults: max_depth=8, alpha=-inf, beta=inf
-    // Base case – terminal position or maximum depth reached
-    if board.is_win() || board.is_draw() || max_depth == 0 {
-        return board.evaluate(original_player);
+    ctx.nodes += 1;
+    ctx.observer.node(ply);
+    if ctx.out_of_time() {
+        return 0.0; // result is thrown away by the caller
     }
-    let mut a = alpha;
-    let mut b = beta;
-    // Recursive case - maximize your gains or minimize the opponent's gains
-    if maximizing {
-        for m in board.legal_moves() {
-            let result = alphabeta(
-                board.make_move(m),
-                false,
-                original_player,
-                max_depth - 1,
-                a,
-                b,
-            );
-            a = f32_max(result, a);
-            if b <= a {
-                break;
-            }
-        }
+    if let Some(outcome) = board.outcome() {
+        return outcome_score(outcome, ply);
+    }
+    ctx.depth_limited = true;
+    ctx.observer.leaf(ply);
+    let mut a = alpha.max(board.evaluate(board.turn()));
+    if a >= beta || max_depth == 0 {
         return a;
-    } else {
-        // minimizing
-        for m in board.legal_moves() {
-            let result = alphabeta(
-                board.make_move(m),
-                true,
-                original_player,
-                max_depth - 1,
-                a,
-                b,
-            );
-            b = f32_min(result, b);
-            if b <= a {
-                break;
-            }
+    }
+    let mut moves: Vec<Move> = board
+        .legal_moves()
+        .into_iter()
+        .filter(|&m| board.is_noisy(m))
+        .collect();
+    ctx.order_moves(board, &mut moves, ply, None);
+    for (i, m) in moves.into_iter().enumerate() {
+        let child = board.make_move(m);
+        let result = if child.turn() == board.turn() {
+            quiesce(&child, max_depth - 1, ply + 1, a, beta, ctx)
+        } else {
+            -quiesce(&child, max_depth - 1, ply + 1, -beta, -a, ctx)
+        };
+        a = a.max(result);
+        if a >= beta {
+            ctx.observer.cutoff(ply, i);
+            break;
+        }
+    }
+    a
+}
+
+// Search every root move to max_depth, or return None if the search ran
+// out of time before finishing
+fn negamax_root<B: Board<P>, P: Piece, O: SearchObserver>(
+    board: &B,
+    max_depth: i32,
+    ctx: &mut SearchContext<O>,
+) -> Option<SearchResult> {
+    let mut result = SearchResult {
+        best_move: None,
+        score: f32::NEG_INFINITY,
+        pv: Vec::new(),
+        nodes: 0,
+        depth: max_depth,
+    };
+    let key = board.hash_key();
+    let mut moves = board.legal_moves();
+    let first = ctx.probe(key, 0).and_then(|e| e.best_move).or(ctx.pv.first().cloned());
+    ctx.order_moves(board, &mut moves, 0, first);
+    let mut line = Vec::new();
+    for m in moves {
+        // moves that cannot beat the best score fail low without an exact one
+        line.clear();
+        ctx.on_pv = ctx.pv.first() == Some(&m);
+        let eval = child_score(
+            board,
+            &board.make_move(m),
+            max_depth,
+            1,
+            result.score,
+            f32::INFINITY,
+            ctx,
+            &mut line,
+        );
+        if ctx.aborted {
+            return None;
         }
-        return b;
+        if eval > result.score {
+            result.score = eval;
+            result.best_move = Some(m);
+            result.pv.clear();
+            result.pv.push(m);
+            result.pv.extend_from_slice(&line);
+        }
+    }
+    if result.best_move.is_some() {
+        ctx.store(
+            key,
+            0,
+            Entry {
+                key: 0,
+                depth: max_depth.saturating_add(1),
+                score: result.score,
+                bound: Bound::Exact,
+                best_move: result.best_move,
+            },
+        );
     }
+    result.nodes = ctx.nodes;
+    Some(result)
 }
 
 // Find the best possible move in the current position
@@ -106,7 +546,12 @@ pub fn find_best_move_minimax<B: Board<P>, P: Piece>(board: B, max_depth: i32) -
     //let alpha = f32::NEG_INFINITY;
     //let beta = f32::INFINITY;
     for &m in board.legal_moves().iter() {
-        let result = minimax(board.make_move(m), false, board.turn(), max_depth);
+        let child = board.make_move(m);
+        let result = if child.turn() == board.turn() {
+            minimax(child, max_depth, 1)
+        } else {
+            -minimax(child, max_depth, 1)
+        };
         //eprintln!("result: {} best_eval: {} depth: {}", result, best_eval, max_depth);
         if result > best_eval {
             best_eval = result;
@@ -117,27 +562,234 @@ pub fn find_best_move_minimax<B: Board<P>, P: Piece>(board: B, max_depth: i32) -
 }
 
 // Find the best possible move in the current position
-// looking up to max_depth ahead
+// looking up to max_depth ahead. Returns -1 if there are no legal moves.
 pub fn find_best_move<B: Board<P>, P: Piece>(board: B, max_depth: i32) -> Move {
     // default: max_depth=8
-    let mut best_eval = f32::NEG_INFINITY;
-    let mut best_move: Move = -1;
-    let alpha = f32::NEG_INFINITY;
-    let beta = f32::INFINITY;
-    for &m in board.legal_moves().iter() {
-        let result = alphabeta(
-            board.make_move(m),
-            false,
-            board.turn(),
-            max_depth,
-            alpha,
-            beta,
-        );
-        //eprintln!("result: {} best_eval: {} depth: {}", result, best_eval, max_depth);
-        if result > best_eval {
-            best_eval = result;
-            best_move = m;
+    analyze(board, max_depth).best_move.unwrap_or(-1)
+}
+
+// Search the current position up to max_depth ahead and report what the
+// engine found, not just the move
+pub fn analyze<B: Board<P>, P: Piece>(board: B, max_depth: i32) -> SearchResult {
+    let mut no_observer = ();
+    let mut ctx = SearchContext::new(None, None, MoveOrdering::NONE, &mut no_observer);
+    negamax_root(&board, max_depth, &mut ctx).expect("search without a deadline completes")
+}
+
+// Alpha-beta search engine that can keep a transposition table between
+// moves. A table should only ever be used for positions of one game.
+pub struct Searcher {
+    pub max_depth: i32,
+    pub time_limit: Option<Duration>,
+    pub table: Option<TranspositionTable>,
+    // parallel searches return the same move on every run, at some cost
+    pub deterministic: bool,
+    pub move_ordering: MoveOrdering,
+    // extra plies of noisy moves (see Board::is_noisy) to search past
+    // max_depth, 0 to evaluate positions at max_depth as they are
+    pub quiescence_depth: i32,
+}
+
+impl Default for Searcher {
+    fn default() -> Searcher {
+        Searcher {
+            max_depth: 8,
+            time_limit: None,
+            table: None,
+            deterministic: false,
+            move_ordering: MoveOrdering::default(),
+            quiescence_depth: 0,
         }
     }
-    best_move
+}
+
+impl Searcher {
+    // Search to depth 1, 2, 3, ... up to max_depth, or until time_limit
+    // runs out, and return the result of the deepest search that finished.
+    // The time limit is checked during the search, so the call returns
+    // shortly after it expires.
+    pub fn search<B: Board<P>, P: Piece>(&mut self, board: &B) -> SearchResult {
+        self.search_with_observer(board, &mut ())
+    }
+
+    // Same as search, reporting progress to observer as it goes
+    pub fn search_with_observer<B, P, O>(&mut self, board: &B, observer: &mut O) -> SearchResult
+    where
+        B: Board<P>,
+        P: Piece,
+        O: SearchObserver,
+    {
+        let started = Instant::now();
+        let deadline = self.time_limit.map(|limit| started + limit);
+        let mut ctx = SearchContext::new(deadline, self.table.as_mut(), self.move_ordering, observer);
+        ctx.quiescence_depth = self.quiescence_depth;
+        let mut best = deepen(board, self.max_depth, |depth| {
+            ctx.depth_limited = false;
+            let result = negamax_root(board, depth, &mut ctx)?;
+            ctx.pv = result.pv.clone();
+            ctx.observer.iteration(&result, started.elapsed());
+            Some((result, ctx.depth_limited))
+        });
+        best.nodes = ctx.nodes;
+        best
+    }
+
+    // Same as search, but the root moves are shared out between threads.
+    // Unless deterministic is set, a thread can skip work once another
+    // has found a good move, which makes the choice between equally good
+    // moves depend on timing. The transposition table is not used.
+    pub fn search_parallel<B, P>(&mut self, board: &B) -> SearchResult
+    where
+        B: Board<P> + Send + Sync,
+        P: Piece,
+    {
+        let deadline = self.time_limit.map(|limit| Instant::now() + limit);
+        let deterministic = self.deterministic;
+        let ordering = self.move_ordering;
+        let quiescence_depth = self.quiescence_depth;
+        let mut nodes = 0;
+        let mut best = deepen(board, self.max_depth, |depth| {
+            parallel_root(board, depth, deadline, deterministic, ordering, quiescence_depth, &mut nodes)
+        });
+        best.nodes = nodes;
+        best
+    }
+}
+
+// Iterative deepening: run search_depth for depth 1, 2, 3, ... up to
+// max_depth and keep the last result. search_depth returns None once it
+// has run out of time, and says whether any line was cut off by depth.
+fn deepen<B: Board<P>, P: Piece, F>(board: &B, max_depth: i32, mut search_depth: F) -> SearchResult
+where
+    F: FnMut(i32) -> Option<(SearchResult, bool)>,
+{
+    // fall back to any legal move if not even depth 1 completes
+    let first_move = board.legal_moves().first().cloned();
+    let mut best = SearchResult {
+        best_move: first_move,
+        score: 0.0,
+        pv: first_move.into_iter().collect(),
+        nodes: 0,
+        depth: 0,
+    };
+    if first_move.is_none() {
+        return best;
+    }
+    for depth in 1..=max_depth {
+        match search_depth(depth) {
+            Some((result, depth_limited)) => {
+                best = result;
+                if !depth_limited {
+                    break; // the whole game tree fit, searching deeper changes nothing
+                }
+            }
+            None => break,
+        }
+    }
+    best
+}
+
+// One root move searched on a worker thread
+struct RootMove {
+    eval: f32,
+    exact: bool, // false if it failed low against another thread's move
+    pv: Vec<Move>,
+    nodes: u64,
+    depth_limited: bool,
+    aborted: bool,
+}
+
+// Search the root moves in parallel, adding the positions visited to nodes.
+// Returns the result and whether any line was cut off by max_depth, or None
+// if the search ran out of time before finishing.
+fn parallel_root<B, P>(
+    board: &B,
+    max_depth: i32,
+    deadline: Option<Instant>,
+    deterministic: bool,
+    ordering: MoveOrdering,
+    quiescence_depth: i32,
+    nodes: &mut u64,
+) -> Option<(SearchResult, bool)>
+where
+    B: Board<P> + Send + Sync,
+    P: Piece,
+{
+    // best score found by any thread so far, as f32 bits
+    let shared_best = AtomicU32::new(f32::NEG_INFINITY.to_bits());
+    let moves = board.legal_moves();
+    let root_moves: Vec<RootMove> = moves
+        .par_iter()
+        .map(|&m| {
+            let alpha = if deterministic {
+                f32::NEG_INFINITY
+            } else {
+                f32::from_bits(shared_best.load(Ordering::Relaxed))
+            };
+            let mut no_observer = ();
+            let mut ctx = SearchContext::new(deadline, None, ordering, &mut no_observer);
+            ctx.quiescence_depth = quiescence_depth;
+            let mut line = Vec::new();
+            let eval = child_score(
+                board,
+                &board.make_move(m),
+                max_depth,
+                1,
+                alpha,
+                f32::INFINITY,
+                &mut ctx,
+                &mut line,
+            );
+            line.insert(0, m);
+            let exact = eval > alpha;
+            if exact && !deterministic {
+                let _ = shared_best.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                    if eval > f32::from_bits(bits) {
+                        Some(eval.to_bits())
+                    } else {
+                        None // another thread already did better
+                    }
+                });
+            }
+            RootMove {
+                eval,
+                exact,
+                pv: line,
+                nodes: ctx.nodes,
+                depth_limited: ctx.depth_limited,
+                aborted: ctx.aborted,
+            }
+        })
+        .collect();
+    *nodes += root_moves.iter().map(|r| r.nodes).sum::<u64>();
+    if root_moves.iter().any(|r| r.aborted) {
+        return None;
+    }
+    let mut result = SearchResult {
+        best_move: None,
+        score: f32::NEG_INFINITY,
+        pv: Vec::new(),
+        nodes: *nodes,
+        depth: max_depth,
+    };
+    // the first of the best moves in move order, as a sequential search finds
+    for (&m, root_move) in moves.iter().zip(root_moves.iter()) {
+        if root_move.exact && root_move.eval > result.score {
+            result.best_move = Some(m);
+            result.score = root_move.eval;
+            result.pv = root_move.pv.clone();
+        }
+    }
+    Some((result, root_moves.iter().any(|r| r.depth_limited)))
+}
+
+// Find the best move that can be found within the time budget, see
+// Searcher::search
+pub fn find_best_move_timed<B: Board<P>, P: Piece>(board: B, budget: Duration) -> SearchResult {
+    let mut searcher = Searcher {
+        max_depth: i32::MAX,
+        time_limit: Some(budget),
+        ..Searcher::default()
+    };
+    searcher.search(&board)
 }
@@ -0,0 +1,146 @@
+//! Multi-player search
+//! Board::turn and Piece::opposite assume two players taking turns. Games
+//! for three or more players number them from 0 instead, and score every
+//! player separately.
+
+use crate::board::Move;
+use crate::minimax::SearchResult;
+
+// Players are numbered 0 to num_players - 1
+pub type Player = usize;
+
+// A position in a game for any number of players
+pub trait MultiplayerBoard: Sized {
+    fn num_players(&self) -> usize;
+    // The player about to move
+    fn current_player(&self) -> Player;
+    fn make_move(&self, location: Move) -> Self;
+    fn legal_moves(&self) -> Vec<Move>;
+    fn is_terminal(&self) -> bool;
+    // How good the position is for each player, indexed by player, higher
+    // is better. At the end of the game these are the final results.
+    fn evaluate(&self) -> Vec<f32>;
+}
+
+// How a player expects the others to play
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Strategy {
+    // Everybody plays for their own score (max^n)
+    MaxN,
+    // Everybody else gangs up on the player to move at the root, which
+    // lets alpha-beta prune
+    Paranoid,
+}
+
+// Depth limited search for multi-player games
+#[derive(Clone, Debug)]
+pub struct MultiplayerSearcher {
+    pub max_depth: i32,
+    pub strategy: Strategy,
+}
+
+impl Default for MultiplayerSearcher {
+    fn default() -> MultiplayerSearcher {
+        MultiplayerSearcher {
+            max_depth: 6,
+            strategy: Strategy::MaxN,
+        }
+    }
+}
+
+impl MultiplayerSearcher {
+    // Find the best move for the player to move. The score is that
+    // player's evaluation at the end of the principal variation.
+    pub fn search<B: MultiplayerBoard>(&self, board: &B) -> SearchResult {
+        let mut nodes = 0;
+        let mut pv = Vec::new();
+        let score = match self.strategy {
+            Strategy::MaxN => {
+                maxn(board, self.max_depth, &mut nodes, &mut pv)[board.current_player()]
+            }
+            Strategy::Paranoid => {
+                let player = board.current_player();
+                paranoid(board, self.max_depth, player, f32::NEG_INFINITY, f32::INFINITY, &mut nodes, &mut pv)
+            }
+        };
+        SearchResult {
+            best_move: pv.first().cloned(),
+            score,
+            pv,
+            nodes,
+            depth: self.max_depth,
+        }
+    }
+}
+
+// Scores of the position for every player when each one picks the move
+// best for themselves. Ties go to the first move found.
+fn maxn<B: MultiplayerBoard>(board: &B, max_depth: i32, nodes: &mut u64, pv: &mut Vec<Move>) -> Vec<f32> {
+    *nodes += 1;
+    pv.clear();
+    if max_depth == 0 || board.is_terminal() {
+        return board.evaluate();
+    }
+    let player = board.current_player();
+    let mut best: Option<Vec<f32>> = None;
+    let mut line = Vec::new();
+    for m in board.legal_moves() {
+        let scores = maxn(&board.make_move(m), max_depth - 1, nodes, &mut line);
+        let better = match &best {
+            Some(b) => scores[player] > b[player],
+            None => true,
+        };
+        if better {
+            best = Some(scores);
+            pv.clear();
+            pv.push(m);
+            pv.extend_from_slice(&line);
+        }
+    }
+    // a player with no moves but the game not over: nothing to choose
+    best.unwrap_or_else(|| board.evaluate())
+}
+
+// Score for player, who maximizes it while everybody else minimizes it,
+// searched with fail-hard alpha-beta
+#[allow(clippy::too_many_arguments)]
+fn paranoid<B: MultiplayerBoard>(
+    board: &B,
+    max_depth: i32,
+    player: Player,
+    mut alpha: f32,
+    mut beta: f32,
+    nodes: &mut u64,
+    pv: &mut Vec<Move>,
+) -> f32 {
+    *nodes += 1;
+    pv.clear();
+    let moves = board.legal_moves();
+    if max_depth == 0 || board.is_terminal() || moves.is_empty() {
+        return board.evaluate()[player];
+    }
+    let maximizing = board.current_player() == player;
+    let mut line = Vec::new();
+    for m in moves {
+        let score = paranoid(&board.make_move(m), max_depth - 1, player, alpha, beta, nodes, &mut line);
+        let improved = if maximizing { score > alpha } else { score < beta };
+        if improved {
+            if maximizing {
+                alpha = score;
+            } else {
+                beta = score;
+            }
+            pv.clear();
+            pv.push(m);
+            pv.extend_from_slice(&line);
+        }
+        if alpha >= beta {
+            break;
+        }
+    }
+    if maximizing {
+        alpha
+    } else {
+        beta
+    }
+}
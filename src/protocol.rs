@@ -0,0 +1,211 @@
+//! Engine protocol
+//! A line based protocol for driving a Board engine from another program,
+//! in the style of UCI. Commands arrive one per line and every answer
+//! starts with a keyword, so a GUI or test script can read the replies
+//! back reliably.
+//!
+//! ```text
+//! protocol                      -> id name <game> / protocolok
+//! isready                       -> readyok
+//! position startpos [moves 3 8 ...]
+//! position notation <position> [moves 3 8 ...]
+//! move <move>                   play a move in the current position
+//! go [depth <plies>] [movetime <ms>]
+//!                               -> info depth 1 score 0.0 nodes 7 time 0 pv 4 ...
+//!                               -> bestmove <move> (or bestmove none)
+//! legalmoves                    -> legalmoves 0 1 2 ...
+//! show                          -> notation <position> (and outcome <result>)
+//! quit
+//! ```
+//!
+//! Scores are from the side to move's point of view; forced results are
+//! given as `score win <plies>` or `score loss <plies>`. Anything that
+//! cannot be carried out is answered with `error <reason>` and leaves the
+//! position alone.
+
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+use crate::board::{Board, Move, Notation, Outcome, Piece};
+use crate::minimax::{SearchObserver, SearchResult, Searcher, WIN_SCORE};
+
+// Depth for searches limited by time only, deep enough to never be reached
+const UNLIMITED_DEPTH: i32 = 100;
+
+// Writes an info line after every iteration of a search
+struct InfoWriter<'a, W> {
+    output: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<'a, W: Write> SearchObserver for InfoWriter<'a, W> {
+    fn iteration(&mut self, result: &SearchResult, elapsed: Duration) {
+        if self.error.is_some() {
+            return;
+        }
+        let pv: Vec<String> = result.pv.iter().map(|m| m.to_string()).collect();
+        if let Err(e) = writeln!(
+            self.output,
+            "info depth {} score {} nodes {} time {} pv {}",
+            result.depth,
+            format_score(result.score),
+            result.nodes,
+            elapsed.as_millis(),
+            pv.join(" ")
+        ) {
+            self.error = Some(e);
+        }
+    }
+}
+
+fn format_score(score: f32) -> String {
+    // scores this close to WIN_SCORE are forced results counted in plies
+    if score > WIN_SCORE / 2.0 {
+        format!("win {}", (WIN_SCORE - score).round())
+    } else if score < -WIN_SCORE / 2.0 {
+        format!("loss {}", (WIN_SCORE + score).round())
+    } else {
+        format!("{:.1}", score + 0.0) // no -0.0
+    }
+}
+
+// Answer commands from input on output until quit or the end of input.
+// startpos is the usual starting position and game the name to identify
+// the engine by. Only failures to read or write are errors, mistakes in
+// the commands are answered on output.
+pub fn serve<B, P, R, W>(game: &str, startpos: B, input: R, mut output: W) -> io::Result<()>
+where
+    B: Board<P> + Notation + Clone,
+    P: Piece,
+    R: BufRead,
+    W: Write,
+{
+    let mut board = startpos.clone();
+    let mut searcher = Searcher::default();
+    for line in input.lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let reply = match words.as_slice() {
+            [] => Ok(()),
+            ["quit"] => break,
+            ["protocol"] => {
+                writeln!(output, "id name {}", game)?;
+                writeln!(output, "protocolok")?;
+                Ok(())
+            }
+            ["isready"] => {
+                writeln!(output, "readyok")?;
+                Ok(())
+            }
+            ["position", rest @ ..] => set_position(&startpos, rest).map(|b| board = b),
+            ["move", location] => play_moves(&board, &[location]).map(|b| board = b),
+            ["go", limits @ ..] => match parse_limits(limits) {
+                Ok(_) if board.outcome().is_some() => {
+                    writeln!(output, "bestmove none")?;
+                    Ok(())
+                }
+                Ok((depth, time_limit)) => {
+                    searcher.max_depth = depth;
+                    searcher.time_limit = time_limit;
+                    let mut info = InfoWriter { output: &mut output, error: None };
+                    let result = searcher.search_with_observer(&board, &mut info);
+                    if let Some(e) = info.error {
+                        return Err(e);
+                    }
+                    match result.best_move {
+                        Some(m) => writeln!(output, "bestmove {}", m)?,
+                        None => writeln!(output, "bestmove none")?,
+                    }
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+            ["legalmoves"] => {
+                let mut moves = board.legal_moves();
+                moves.sort();
+                let moves: Vec<String> = moves.iter().map(|m| m.to_string()).collect();
+                writeln!(output, "legalmoves {}", moves.join(" "))?;
+                Ok(())
+            }
+            ["show"] => {
+                writeln!(output, "notation {}", board.to_notation())?;
+                if let Some(outcome) = board.outcome() {
+                    let outcome = match outcome {
+                        Outcome::Win => "win",
+                        Outcome::Loss => "loss",
+                        Outcome::Draw => "draw",
+                    };
+                    writeln!(output, "outcome {}", outcome)?;
+                }
+                Ok(())
+            }
+            [command, ..] => Err(format!("unknown command {}", command)),
+        };
+        if let Err(reason) = reply {
+            writeln!(output, "error {}", reason)?;
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
+
+// The board described by the words after "position"
+fn set_position<B, P>(startpos: &B, words: &[&str]) -> Result<B, String>
+where
+    B: Board<P> + Notation + Clone,
+    P: Piece,
+{
+    let moves_at = words.iter().position(|&w| w == "moves").unwrap_or(words.len());
+    let board = match &words[..moves_at] {
+        ["startpos"] => startpos.clone(),
+        ["notation", notation @ ..] if !notation.is_empty() => {
+            notation.join(" ").parse::<B>().map_err(|e| e.to_string())?
+        }
+        _ => return Err("expected position startpos or position notation <position>".to_owned()),
+    };
+    let moves = if moves_at < words.len() { &words[moves_at + 1..] } else { &[] };
+    play_moves(&board, moves)
+}
+
+// board after the moves, if they are all legal
+fn play_moves<B, P>(board: &B, moves: &[&str]) -> Result<B, String>
+where
+    B: Board<P> + Clone,
+    P: Piece,
+{
+    let mut board = board.clone();
+    for word in moves {
+        let location = word.parse::<Move>().map_err(|_| format!("malformed move {}", word))?;
+        if board.outcome().is_some() || !board.legal_moves().contains(&location) {
+            return Err(format!("illegal move {}", location));
+        }
+        board = board.make_move(location);
+    }
+    Ok(board)
+}
+
+// Depth and time limit from the words after "go"
+fn parse_limits(words: &[&str]) -> Result<(i32, Option<Duration>), String> {
+    let mut depth = None;
+    let mut time_limit = None;
+    let mut words = words.iter();
+    let usage = || "expected go [depth <plies>] [movetime <ms>]".to_owned();
+    while let Some(&word) = words.next() {
+        match (word, words.next()) {
+            ("depth", Some(plies)) => match (plies.parse::<i32>(), plies.parse::<u64>()) {
+                (Ok(plies), _) if plies > 0 => depth = Some(plies),
+                // a number, just too big for the search
+                (Err(_), Ok(_)) => return Err(format!("depth must be at most {}", i32::MAX)),
+                _ => return Err(usage()),
+            },
+            ("movetime", Some(ms)) => time_limit = Some(Duration::from_millis(ms.parse().map_err(|_| usage())?)),
+            _ => return Err(usage()),
+        }
+    }
+    let depth = match (depth, time_limit) {
+        (Some(depth), _) => depth,
+        (None, Some(_)) => UNLIMITED_DEPTH,
+        (None, None) => Searcher::default().max_depth,
+    };
+    Ok((depth, time_limit))
+}
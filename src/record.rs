@@ -0,0 +1,406 @@
+//! Game records
+//! A finished (or abandoned) game as a list of moves with tags describing
+//! it, stored as text in the spirit of PGN:
+//!
+//! ```text
+//! [Game "Oware"]
+//! [Depth "5"]
+//! [Result "1-0"]
+//!
+//! 1. 3 8 2. 0 11 3. 5 7 1-0
+//! ```
+//!
+//! Moves are written as the numbers Board::make_move takes. A Position tag
+//! holds the starting position if the game did not start from the usual
+//! one.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use crate::board::{Board, Move, Notation, NotationError, Outcome, Piece};
+
+// How a game ended, from the point of view of the player who moved first
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GameResult {
+    FirstWins,
+    SecondWins,
+    Draw,
+    // Still going, or stopped without a result
+    Unfinished,
+}
+
+impl GameResult {
+    // Result of a game that has reached board, where first is the player
+    // who made the first move
+    pub fn from_board<B: Board<P>, P: Piece>(board: &B, first: P) -> GameResult {
+        let first_to_move = board.turn() == first;
+        match board.outcome() {
+            None => GameResult::Unfinished,
+            Some(Outcome::Draw) => GameResult::Draw,
+            Some(Outcome::Win) if first_to_move => GameResult::FirstWins,
+            Some(Outcome::Loss) if !first_to_move => GameResult::FirstWins,
+            Some(_) => GameResult::SecondWins,
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                GameResult::FirstWins => "1-0",
+                GameResult::SecondWins => "0-1",
+                GameResult::Draw => "1/2-1/2",
+                GameResult::Unfinished => "*",
+            }
+        )
+    }
+}
+
+impl FromStr for GameResult {
+    type Err = RecordError;
+
+    fn from_str(s: &str) -> Result<GameResult, RecordError> {
+        match s {
+            "1-0" => Ok(GameResult::FirstWins),
+            "0-1" => Ok(GameResult::SecondWins),
+            "1/2-1/2" => Ok(GameResult::Draw),
+            "*" => Ok(GameResult::Unfinished),
+            _ => Err(RecordError::BadResult(s.to_owned())),
+        }
+    }
+}
+
+// What went wrong reading or replaying a record
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RecordError {
+    // A tag line that is not `[Name "value"]`
+    BadTag(String),
+    // Something in the move list that is neither a move nor a move number
+    BadMove(String),
+    BadResult(String),
+    // A Position tag that is not a valid position
+    BadPosition(NotationError),
+    // The move at this ply (counting from 0) is not legal in the position
+    // reached by then
+    IllegalMove { ply: usize, location: Move },
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::BadTag(line) => write!(f, "malformed tag: {}", line),
+            RecordError::BadMove(token) => write!(f, "malformed move: {}", token),
+            RecordError::BadResult(token) => write!(f, "malformed result: {}", token),
+            RecordError::BadPosition(e) => write!(f, "{}", e),
+            RecordError::IllegalMove { ply, location } => {
+                write!(f, "illegal move {} at ply {}", location, ply)
+            }
+        }
+    }
+}
+
+impl Error for RecordError {}
+
+// A game as tags, moves and result
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameRecord {
+    // Name and value pairs, written in this order. Result is kept in
+    // result instead.
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<Move>,
+    pub result: GameResult,
+}
+
+impl Default for GameRecord {
+    fn default() -> GameRecord {
+        GameRecord {
+            tags: Vec::new(),
+            moves: Vec::new(),
+            result: GameResult::Unfinished,
+        }
+    }
+}
+
+impl GameRecord {
+    // Empty record of a game of the given name
+    pub fn new(game: &str) -> GameRecord {
+        let mut record = GameRecord::default();
+        record.set_tag("Game", game);
+        record
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    // Set a tag, replacing any earlier value
+    pub fn set_tag<V: ToString>(&mut self, name: &str, value: V) {
+        let value = value.to_string();
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some(tag) => tag.1 = value,
+            None => self.tags.push((name.to_owned(), value)),
+        }
+    }
+
+    // The position in the Position tag, or standard if the game started
+    // from the usual position
+    pub fn initial_position<B: Notation>(&self, standard: B) -> Result<B, RecordError> {
+        match self.tag("Position") {
+            Some(text) => text.parse().map_err(RecordError::BadPosition),
+            None => Ok(standard),
+        }
+    }
+
+    // Positions after each move, starting from initial and checking that
+    // every move is legal
+    pub fn replay<B: Board<P> + Clone, P: Piece>(&self, initial: B) -> Replay<B, P> {
+        Replay {
+            board: initial,
+            moves: self.moves.clone().into_iter(),
+            ply: 0,
+            player: std::marker::PhantomData,
+        }
+    }
+
+    // The position at the end of the record
+    pub fn final_position<B: Board<P> + Clone, P: Piece>(&self, initial: B) -> Result<B, RecordError> {
+        let mut board = initial.clone();
+        for position in self.replay(initial) {
+            board = position?;
+        }
+        Ok(board)
+    }
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.tags {
+            writeln!(f, "[{} \"{}\"]", name, value)?;
+        }
+        writeln!(f, "[Result \"{}\"]", self.result)?;
+        writeln!(f)?;
+        for (i, m) in self.moves.iter().enumerate() {
+            if i % 2 == 0 {
+                write!(f, "{}. ", i / 2 + 1)?;
+            }
+            write!(f, "{} ", m)?;
+        }
+        writeln!(f, "{}", self.result)
+    }
+}
+
+impl FromStr for GameRecord {
+    type Err = RecordError;
+
+    fn from_str(s: &str) -> Result<GameRecord, RecordError> {
+        let mut record = GameRecord::default();
+        let mut lines = s.lines().map(str::trim).peekable();
+        while let Some(line) = lines.next_if(|l| l.starts_with('[')) {
+            let (name, value) = parse_tag(line)?;
+            if name == "Result" {
+                record.result = value.parse()?;
+            } else {
+                record.tags.push((name.to_owned(), value.to_owned()));
+            }
+        }
+        for token in lines.flat_map(str::split_whitespace) {
+            if token.ends_with('.') && token[..token.len() - 1].parse::<u32>().is_ok() {
+                continue; // move number
+            }
+            match token.parse::<Move>() {
+                Ok(m) => record.moves.push(m),
+                Err(_) => record.result = token.parse().map_err(|_| RecordError::BadMove(token.to_owned()))?,
+            }
+        }
+        Ok(record)
+    }
+}
+
+fn parse_tag(line: &str) -> Result<(&str, &str), RecordError> {
+    let bad = || RecordError::BadTag(line.to_owned());
+    let inner = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')).ok_or_else(bad)?;
+    let space = inner.find(' ').ok_or_else(bad)?;
+    let value = inner[space + 1..].trim();
+    let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).ok_or_else(bad)?;
+    Ok((&inner[..space], value))
+}
+
+// Iterator over the positions of a recorded game, from GameRecord::replay
+pub struct Replay<B, P> {
+    board: B,
+    moves: std::vec::IntoIter<Move>,
+    ply: usize,
+    player: std::marker::PhantomData<P>,
+}
+
+impl<B: Board<P> + Clone, P: Piece> Iterator for Replay<B, P> {
+    type Item = Result<B, RecordError>;
+
+    fn next(&mut self) -> Option<Result<B, RecordError>> {
+        let location = self.moves.next()?;
+        if !self.board.legal_moves().contains(&location) {
+            // stop here, there is no position to go on from
+            self.moves = Vec::new().into_iter();
+            return Some(Err(RecordError::IllegalMove { ply: self.ply, location }));
+        }
+        self.board = self.board.make_move(location);
+        self.ply += 1;
+        Some(Ok(self.board.clone()))
+    }
+}
+
+// Read every record in text holding any number of games one after the
+// other, as written by writing each record followed by a blank line
+pub fn parse_records(s: &str) -> Result<Vec<GameRecord>, RecordError> {
+    let mut records = Vec::new();
+    let mut game = String::new();
+    let mut in_moves = false;
+    for line in s.lines() {
+        let is_tag = line.trim_start().starts_with('[');
+        if is_tag && in_moves {
+            records.push(game.parse()?);
+            game.clear();
+        }
+        in_moves = !is_tag && (in_moves || !line.trim().is_empty());
+        game.push_str(line);
+        game.push('\n');
+    }
+    if !game.trim().is_empty() {
+        records.push(game.parse()?);
+    }
+    Ok(records)
+}
+
+// Show each game on output one move at a time, going on to the next move
+// for every line of input (and straight through once input runs out).
+// Games start from their Position tag if they have one, otherwise from
+// standard.
+pub fn step_through<B, P, R, W>(records: &[GameRecord], standard: B, input: R, mut output: W) -> io::Result<()>
+where
+    B: Board<P> + Notation + Clone,
+    P: Piece,
+    R: BufRead,
+    W: Write,
+{
+    let mut lines = input.lines();
+    for record in records {
+        let initial = match record.initial_position(standard.clone()) {
+            Ok(initial) => initial,
+            Err(e) => {
+                writeln!(output, "{}", e)?;
+                continue;
+            }
+        };
+        writeln!(output, "{}", initial)?;
+        for (m, position) in record.moves.iter().zip(record.replay(initial)) {
+            if let Some(line) = lines.next() {
+                line?;
+            }
+            match position {
+                Ok(board) => writeln!(output, "Move {}\n{}", m, board)?,
+                Err(e) => writeln!(output, "{}", e)?,
+            }
+        }
+        writeln!(output, "Result {}", record.result)?;
+    }
+    Ok(())
+}
+
+// Step through the games saved in the file at path on stdin and stdout
+pub fn replay_file<B, P>(path: &str, standard: B) -> Result<(), Box<dyn Error>>
+where
+    B: Board<P> + Notation + Clone,
+    P: Piece,
+{
+    let records = parse_records(&std::fs::read_to_string(path)?)?;
+    let stdin = io::stdin();
+    step_through(&records, standard, stdin.lock(), io::stdout())?;
+    Ok(())
+}
+
+// What a game binary is asked to do by the options it shares with the
+// others
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Mode {
+    // play a game, saving it to the file if there is one
+    Play(Option<String>),
+    // step through the games saved in the file
+    Replay(String),
+    // answer the engine protocol on stdin and stdout
+    Protocol,
+}
+
+impl Default for Mode {
+    fn default() -> Mode {
+        Mode::Play(None)
+    }
+}
+
+impl Mode {
+    // Take arg if it is --save FILE, --replay FILE or --protocol, reading
+    // the file from args. Ok(false) if it is some other option.
+    pub fn parse_option<'a, I>(&mut self, arg: &str, args: &mut I) -> Result<bool, String>
+    where
+        I: Iterator<Item = &'a String>,
+    {
+        let mut path = || args.next().cloned().ok_or(format!("{} needs a file", arg));
+        *self = match arg {
+            "--save" => Mode::Play(Some(path()?)),
+            "--replay" => Mode::Replay(path()?),
+            "--protocol" => Mode::Protocol,
+            _ => return Ok(false),
+        };
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut record = GameRecord::new("Oware");
+        record.set_tag("Depth", 5);
+        record.moves = vec![3, 8, 0, 11, 5];
+        record.result = GameResult::SecondWins;
+        let text = record.to_string();
+        assert_eq!(
+            text,
+            "[Game \"Oware\"]\n[Depth \"5\"]\n[Result \"0-1\"]\n\n1. 3 8 2. 0 11 3. 5 0-1\n"
+        );
+        assert_eq!(text.parse::<GameRecord>(), Ok(record.clone()));
+        let two = format!("{}\n{}", text, text);
+        assert_eq!(parse_records(&two), Ok(vec![record.clone(), record]));
+    }
+
+    #[test]
+    fn test_mode() {
+        let args: Vec<String> = ["--save", "game.txt", "--rows", "--replay"].iter().map(|a| a.to_string()).collect();
+        let mut args = args.iter();
+        let mut mode = Mode::default();
+        assert_eq!(mode.parse_option(args.next().unwrap(), &mut args), Ok(true));
+        assert_eq!(mode, Mode::Play(Some("game.txt".to_owned())));
+        assert_eq!(mode.parse_option(args.next().unwrap(), &mut args), Ok(false));
+        assert!(mode.parse_option(args.next().unwrap(), &mut args).is_err());
+        assert_eq!(mode.parse_option("--protocol", &mut args), Ok(true));
+        assert_eq!(mode, Mode::Protocol);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            "[Game Oware]\n\n1. 3 *".parse::<GameRecord>(),
+            Err(RecordError::BadTag("[Game Oware]".to_owned()))
+        );
+        assert_eq!(
+            "[Game \"Oware\"]\n\n1. 3 x *".parse::<GameRecord>(),
+            Err(RecordError::BadMove("x".to_owned()))
+        );
+    }
+}
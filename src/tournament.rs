@@ -0,0 +1,360 @@
+//! Engine matches
+//! Plays two engines against each other on any Board, swapping colors
+//! every game, and says how much stronger one is than the other: the
+//! difference in Elo rating with its error bars, and optionally a
+//! sequential probability ratio test (SPRT) that stops the match as soon as
+//! the result is clear.
+
+use std::fmt;
+
+use rayon::prelude::*;
+
+use crate::board::{Board, Move, Piece};
+use crate::mcts::Mcts;
+use crate::minimax::Searcher;
+use crate::record::{GameRecord, GameResult};
+
+// Anything that picks moves
+pub trait Engine<B: Board<P>, P: Piece> {
+    // The move to play, or None if there is none
+    fn select_move(&mut self, board: &B) -> Option<Move>;
+}
+
+impl<B: Board<P>, P: Piece> Engine<B, P> for Searcher {
+    fn select_move(&mut self, board: &B) -> Option<Move> {
+        self.search(board).best_move
+    }
+}
+
+impl<B: Board<P> + Clone, P: Piece> Engine<B, P> for Mcts {
+    fn select_move(&mut self, board: &B) -> Option<Move> {
+        self.search(board).best_move
+    }
+}
+
+// Engines that are just functions, e.g. a search over a board wrapper
+// with a different evaluation
+impl<B: Board<P>, P: Piece, F: FnMut(&B) -> Option<Move>> Engine<B, P> for F {
+    fn select_move(&mut self, board: &B) -> Option<Move> {
+        self(board)
+    }
+}
+
+// Wins, draws and losses of one engine against the other
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Score {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+// 95% of a normal distribution is within this many standard deviations
+const Z_95: f64 = 1.959_964;
+
+// Rating difference that makes score the expected score per game
+fn elo_from_score(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+// Expected score per game of an engine elo points stronger than its opponent
+fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    // Points per game, counting draws as half a point
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + 0.5 * self.draws as f64) / self.games() as f64
+    }
+
+    // Variance of the points from a single game
+    fn variance(&self) -> f64 {
+        let n = self.games() as f64;
+        let s = self.score();
+        (self.wins as f64 * (1.0 - s).powi(2)
+            + self.draws as f64 * (0.5 - s).powi(2)
+            + self.losses as f64 * s.powi(2))
+            / n
+    }
+
+    // Estimated rating difference, None before the first game or while
+    // one side has won every game
+    pub fn elo(&self) -> Option<f64> {
+        let s = self.score();
+        if s > 0.0 && s < 1.0 {
+            Some(elo_from_score(s))
+        } else {
+            None
+        }
+    }
+
+    // 95% confidence interval of the rating difference, with infinite
+    // ends while the interval reaches a score of 0 or 1
+    pub fn elo_interval(&self) -> Option<(f64, f64)> {
+        if self.games() == 0 {
+            return None;
+        }
+        let s = self.score();
+        let margin = Z_95 * (self.variance() / self.games() as f64).sqrt();
+        let bound = |score: f64| {
+            if score <= 0.0 {
+                f64::NEG_INFINITY
+            } else if score >= 1.0 {
+                f64::INFINITY
+            } else {
+                elo_from_score(score)
+            }
+        };
+        Some((bound(s - margin), bound(s + margin)))
+    }
+
+    fn add(&mut self, result: GameResult, a_first: bool) {
+        match (result, a_first) {
+            (GameResult::FirstWins, true) | (GameResult::SecondWins, false) => self.wins += 1,
+            (GameResult::FirstWins, false) | (GameResult::SecondWins, true) => self.losses += 1,
+            _ => self.draws += 1,
+        }
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "+{} ={} -{}", self.wins, self.draws, self.losses)?;
+        if let (Some(elo), Some((low, high))) = (self.elo(), self.elo_interval()) {
+            write!(f, "  Elo {:.1} [{:.1}, {:.1}]", elo, low, high)?;
+        }
+        Ok(())
+    }
+}
+
+// Sequential probability ratio test of whether engine A is elo0 or elo1
+// points stronger than engine B. alpha is the chance of wrongly accepting
+// elo1 and beta of wrongly accepting elo0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+// Where a test has got to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SprtDecision {
+    AcceptH0,
+    AcceptH1,
+    // Not enough games yet to tell
+    Continue,
+}
+
+impl Sprt {
+    // The usual test for a change that should gain elo1 points: is it
+    // no better (0) or really better (elo1), with 5% error either way
+    pub fn new(elo1: f64) -> Sprt {
+        Sprt {
+            elo0: 0.0,
+            elo1,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+
+    // Log likelihood ratio of elo1 over elo0 for score, using the normal
+    // approximation to the distribution of game results
+    pub fn llr(&self, score: &Score) -> f64 {
+        let variance = score.variance();
+        if score.games() == 0 || variance == 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (score_from_elo(self.elo0), score_from_elo(self.elo1));
+        score.games() as f64 * (s1 - s0) * (2.0 * score.score() - s0 - s1) / (2.0 * variance)
+    }
+
+    pub fn decision(&self, score: &Score) -> SprtDecision {
+        let llr = self.llr(score);
+        if llr >= ((1.0 - self.beta) / self.alpha).ln() {
+            SprtDecision::AcceptH1
+        } else if llr <= (self.beta / (1.0 - self.alpha)).ln() {
+            SprtDecision::AcceptH0
+        } else {
+            SprtDecision::Continue
+        }
+    }
+}
+
+// What happened in a match
+#[derive(Clone, Debug)]
+pub struct MatchReport {
+    // From engine A's point of view
+    pub score: Score,
+    // Every game in the order played, with tags saying which engine moved
+    // first
+    pub records: Vec<GameRecord>,
+    // Where the SPRT, if there was one, ended up
+    pub sprt: Option<SprtDecision>,
+}
+
+// Settings for a match between engines A and B
+#[derive(Clone, Debug)]
+pub struct Tournament {
+    // Stop after this many games
+    pub max_games: u32,
+    // Games still going after this many moves are decided by the sign of
+    // Board::evaluate for the first player
+    pub max_moves: u32,
+    // Stop early once this test is decided
+    pub sprt: Option<Sprt>,
+    // Games played at the same time, and between SPRT checks
+    pub batch_size: u32,
+    // Name for the Game tag of the records
+    pub game: String,
+}
+
+impl Default for Tournament {
+    fn default() -> Tournament {
+        Tournament {
+            max_games: 1000,
+            max_moves: 400,
+            sprt: None,
+            batch_size: 2 * rayon::current_num_threads() as u32,
+            game: String::new(),
+        }
+    }
+}
+
+impl Tournament {
+    // Play a match from the given starting positions, each played twice
+    // so that both engines get both colors. The engines are made afresh
+    // for every game from its number, so nothing carries over from one
+    // game to the next and randomized engines can be seeded. Panics if
+    // there are no openings.
+    pub fn run<B, P, EA, EB, FA, FB>(&self, new_a: FA, new_b: FB, openings: &[B]) -> MatchReport
+    where
+        B: Board<P> + Clone + Send + Sync,
+        P: Piece,
+        EA: Engine<B, P>,
+        EB: Engine<B, P>,
+        FA: Fn(u32) -> EA + Sync,
+        FB: Fn(u32) -> EB + Sync,
+    {
+        assert!(!openings.is_empty(), "a match needs at least one opening, e.g. the initial position");
+        let mut report = MatchReport {
+            score: Score::default(),
+            records: Vec::new(),
+            sprt: self.sprt.map(|_| SprtDecision::Continue),
+        };
+        let mut played = 0;
+        while played < self.max_games {
+            let batch = self.batch_size.max(1).min(self.max_games - played);
+            let records: Vec<GameRecord> = (played..played + batch)
+                .into_par_iter()
+                .map(|game| {
+                    let start = &openings[(game / 2) as usize % openings.len()];
+                    let a_first = game % 2 == 0;
+                    let mut record = if a_first {
+                        self.play_game(&mut new_a(game), &mut new_b(game), start.clone())
+                    } else {
+                        self.play_game(&mut new_b(game), &mut new_a(game), start.clone())
+                    };
+                    record.set_tag("Round", game + 1);
+                    record.set_tag("First", if a_first { "A" } else { "B" });
+                    record.set_tag("Second", if a_first { "B" } else { "A" });
+                    record
+                })
+                .collect();
+            for (game, record) in (played..).zip(records) {
+                report.score.add(record.result, game % 2 == 0);
+                report.records.push(record);
+            }
+            played += batch;
+            if let Some(sprt) = &self.sprt {
+                let decision = sprt.decision(&report.score);
+                report.sprt = Some(decision);
+                if decision != SprtDecision::Continue {
+                    break;
+                }
+            }
+        }
+        report
+    }
+
+    // Play one game between first and second, starting from board
+    pub fn play_game<B, P, E1, E2>(&self, first: &mut E1, second: &mut E2, board: B) -> GameRecord
+    where
+        B: Board<P>,
+        P: Piece,
+        E1: Engine<B, P>,
+        E2: Engine<B, P>,
+    {
+        let mut record = GameRecord::new(&self.game);
+        let first_player = board.turn();
+        let mut board = board;
+        for _ in 0..self.max_moves {
+            if board.outcome().is_some() {
+                break;
+            }
+            let next_move = if board.turn() == first_player {
+                first.select_move(&board)
+            } else {
+                second.select_move(&board)
+            };
+            match next_move {
+                Some(m) => {
+                    board = board.make_move(m);
+                    record.moves.push(m);
+                }
+                None => break,
+            }
+        }
+        record.result = GameResult::from_board(&board, first_player);
+        if record.result == GameResult::Unfinished {
+            let eval = board.evaluate(first_player);
+            record.result = if eval > 0.0 {
+                GameResult::FirstWins
+            } else if eval < 0.0 {
+                GameResult::SecondWins
+            } else {
+                GameResult::Draw
+            };
+        }
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elo() {
+        let even = Score { wins: 30, draws: 40, losses: 30 };
+        assert_eq!(even.elo(), Some(0.0));
+        let (low, high) = even.elo_interval().unwrap();
+        assert!(low < 0.0 && high > 0.0 && (low + high).abs() < 1e-9);
+        // 75% of the points is about 191 Elo
+        let strong = Score { wins: 70, draws: 10, losses: 20 };
+        assert!((strong.elo().unwrap() - 190.85).abs() < 0.01);
+        let (low, high) = strong.elo_interval().unwrap();
+        assert!(low > 0.0 && low < 190.85 && high > 190.85);
+        let perfect = Score { wins: 10, draws: 0, losses: 0 };
+        assert_eq!(perfect.elo(), None);
+    }
+
+    #[test]
+    fn test_sprt() {
+        let sprt = Sprt::new(10.0);
+        let even = Score { wins: 30, draws: 40, losses: 30 };
+        assert_eq!(sprt.decision(&even), SprtDecision::Continue);
+        assert!(sprt.llr(&even) < 0.0);
+        let many_even = Score { wins: 3000, draws: 4000, losses: 3000 };
+        assert_eq!(sprt.decision(&many_even), SprtDecision::AcceptH0);
+        let strong = Score { wins: 70, draws: 10, losses: 20 };
+        assert_eq!(sprt.decision(&strong), SprtDecision::Continue);
+        let more_strong = Score { wins: 140, draws: 20, losses: 40 };
+        assert_eq!(sprt.decision(&more_strong), SprtDecision::AcceptH1);
+    }
+}
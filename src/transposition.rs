@@ -0,0 +1,111 @@
+//! Transposition table
+//! Caches alpha-beta results by position hash so that positions reached
+//! through different move orders are only searched once
+
+use crate::board::Move;
+use std::mem;
+
+// How an entry's score relates to the true value of the position
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Bound {
+    Exact,
+    Lower, // true value >= score (the search failed high)
+    Upper, // true value <= score (the search failed low)
+}
+
+// Result of searching one position, scored for the side to move
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entry {
+    pub key: u64,
+    pub depth: i32,
+    pub score: f32,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+}
+
+// Fixed-size, direct-mapped table of search results
+#[derive(Clone, Debug)]
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+}
+
+impl TranspositionTable {
+    // Create a table using at most `max_bytes` of memory for its entries
+    pub fn new(max_bytes: usize) -> TranspositionTable {
+        let size = (max_bytes / mem::size_of::<Option<Entry>>()).max(1);
+        TranspositionTable {
+            entries: vec![None; size],
+        }
+    }
+
+    // Number of entries the table can hold
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    // Look up the entry for a position, if it is still in the table
+    pub fn get(&self, key: u64) -> Option<&Entry> {
+        match &self.entries[self.index(key)] {
+            Some(entry) if entry.key == key => Some(entry),
+            _ => None,
+        }
+    }
+
+    // Store an entry, replacing whatever shares its slot unless that is
+    // a deeper result for the same position
+    pub fn insert(&mut self, entry: Entry) {
+        let index = self.index(entry.key);
+        if let Some(old) = &self.entries[index] {
+            if old.key == entry.key && old.depth > entry.depth {
+                return;
+            }
+        }
+        self.entries[index] = Some(entry);
+    }
+
+    pub fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: u64, depth: i32) -> Entry {
+        Entry {
+            key,
+            depth,
+            score: 1.0,
+            bound: Bound::Exact,
+            best_move: Some(3),
+        }
+    }
+
+    #[test]
+    fn respects_memory_cap() {
+        let table = TranspositionTable::new(1 << 16);
+        assert!(table.capacity() * mem::size_of::<Option<Entry>>() <= 1 << 16);
+        assert_eq!(TranspositionTable::new(0).capacity(), 1);
+    }
+
+    #[test]
+    fn insert_and_replace() {
+        let mut table = TranspositionTable::new(0);
+        table.insert(entry(7, 4));
+        assert_eq!(table.get(7), Some(&entry(7, 4)));
+        table.insert(entry(7, 2)); // shallower result for the same position is dropped
+        assert_eq!(table.get(7).unwrap().depth, 4);
+        table.insert(entry(8, 1)); // other positions always replace
+        assert_eq!(table.get(7), None);
+        assert_eq!(table.get(8), Some(&entry(8, 1)));
+        table.clear();
+        assert_eq!(table.get(8), None);
+    }
+}